@@ -0,0 +1,25 @@
+-- Your SQL goes here
+
+# 消息表, message_type: 0 room, 1 p2p, 3 broadcast
+CREATE TABLE IF NOT EXISTS messages(
+    `message_id` BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    `from_user` INT NOT NULL,
+    `message_type` TINYINT NOT NULL,
+    `room_name` VARCHAR(50) NULL DEFAULT NULL,
+    `to_user` INT NULL DEFAULT NULL,
+    `content` TEXT NOT NULL,
+    `create_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX `idx_room_time` (`room_name`, `create_time`),
+    INDEX `idx_peer` (`from_user`, `to_user`),
+    FULLTEXT INDEX `ft_content` (`content`) WITH PARSER ngram,
+    FOREIGN KEY (`from_user`) REFERENCES users(`user_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
+
+# 房间成员
+CREATE TABLE IF NOT EXISTS room_members(
+    `room_name` VARCHAR(50) NOT NULL,
+    `user_id` INT NOT NULL,
+    `join_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (`room_name`, `user_id`),
+    FOREIGN KEY (`user_id`) REFERENCES users(`user_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
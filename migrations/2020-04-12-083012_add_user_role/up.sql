@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+# role: 0 member, 1 moderator, 2 admin
+# last_broadcast_id: 已送达该用户的最后一条广播, 上线时补发之后的广播
+ALTER TABLE users
+    ADD COLUMN `role` TINYINT NOT NULL DEFAULT 0,
+    ADD COLUMN `last_broadcast_id` BIGINT NOT NULL DEFAULT 0;
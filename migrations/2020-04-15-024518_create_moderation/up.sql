@@ -0,0 +1,32 @@
+-- Your SQL goes here
+
+# 房间内角色 role: 0 member, 1 manager, 2 owner
+ALTER TABLE room_members
+    ADD COLUMN `role` TINYINT NOT NULL DEFAULT 0;
+
+# 禁言/封禁, kind: 0 mute, 1 ban; room_name 为 NULL 时为全站范围, 全站封禁即停用账号
+# expire_time 为 NULL 时永久有效
+CREATE TABLE IF NOT EXISTS sanctions(
+    `sanction_id` BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    `room_name` VARCHAR(50) NULL DEFAULT NULL,
+    `user_id` INT NOT NULL,
+    `kind` TINYINT NOT NULL,
+    `expire_time` TIMESTAMP NULL DEFAULT NULL,
+    `create_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX `idx_user_kind` (`user_id`, `kind`),
+    FOREIGN KEY (`user_id`) REFERENCES users(`user_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
+
+# 管理操作记录
+CREATE TABLE IF NOT EXISTS audit_logs(
+    `log_id` BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    `operator` INT NOT NULL,
+    `action` VARCHAR(20) NOT NULL,
+    `target_user` INT NOT NULL,
+    `room_name` VARCHAR(50) NULL DEFAULT NULL,
+    `detail` VARCHAR(255) NULL DEFAULT NULL,
+    `create_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX `idx_target` (`target_user`, `create_time`),
+    FOREIGN KEY (`operator`) REFERENCES users(`user_id`),
+    FOREIGN KEY (`target_user`) REFERENCES users(`user_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+# 内容过滤标记的消息, 供人工复核
+CREATE TABLE IF NOT EXISTS message_flags(
+    `flag_id` BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    `message_id` BIGINT NOT NULL,
+    `filter_name` VARCHAR(50) NOT NULL,
+    `reason` VARCHAR(255) NOT NULL,
+    `create_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`message_id`) REFERENCES messages(`message_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+# 用户拉黑, blocker 不再收到 blocked 的单聊消息, 也看不到其在线状态
+CREATE TABLE IF NOT EXISTS user_blocks(
+    `blocker` INT NOT NULL,
+    `blocked` INT NOT NULL,
+    `create_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (`blocker`, `blocked`),
+    FOREIGN KEY (`blocker`) REFERENCES users(`user_id`),
+    FOREIGN KEY (`blocked`) REFERENCES users(`user_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+
+# 好友申请 state: 0 pending, 1 accepted, 2 declined
+CREATE TABLE IF NOT EXISTS contact_requests(
+    `request_id` BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    `from_user` INT NOT NULL,
+    `to_user` INT NOT NULL,
+    `state` TINYINT NOT NULL DEFAULT 0,
+    `message` VARCHAR(255) NULL DEFAULT NULL,
+    `create_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    `updata_time` TIMESTAMP NULL DEFAULT NULL ON UPDATE CURRENT_TIMESTAMP,
+    UNIQUE KEY `uk_from_to` (`from_user`, `to_user`),
+    FOREIGN KEY (`from_user`) REFERENCES users(`user_id`),
+    FOREIGN KEY (`to_user`) REFERENCES users(`user_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
+
+# 好友关系, 双向各存一条
+CREATE TABLE IF NOT EXISTS contacts(
+    `user_id` INT NOT NULL,
+    `contact_id` INT NOT NULL,
+    `create_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (`user_id`, `contact_id`),
+    FOREIGN KEY (`user_id`) REFERENCES users(`user_id`),
+    FOREIGN KEY (`contact_id`) REFERENCES users(`user_id`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;
+
+# 只允许好友发起单聊
+ALTER TABLE users
+    ADD COLUMN `contacts_only` BOOLEAN NOT NULL DEFAULT FALSE;
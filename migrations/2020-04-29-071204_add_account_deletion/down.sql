@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users
+    DROP INDEX `idx_purge_time`,
+    DROP COLUMN `purged`,
+    DROP COLUMN `purge_time`;
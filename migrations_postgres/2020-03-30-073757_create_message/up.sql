@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+-- 用户表, 时间均为 UTC, 与 MySQL 的 TIMESTAMP 一致
+CREATE TABLE IF NOT EXISTS users(
+    user_id SERIAL PRIMARY KEY NOT NULL,
+    user_name VARCHAR(50) UNIQUE NOT NULL,
+    passwd VARCHAR(50) NOT NULL,
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    updata_time TIMESTAMP NULL DEFAULT NULL,
+    delete_time TIMESTAMP NULL DEFAULT NULL
+);
+
+-- 对应 MySQL 的 AUTO_INCREMENT=100000
+ALTER SEQUENCE users_user_id_seq RESTART WITH 100000;
+
+-- 对应 MySQL 的 ON UPDATE CURRENT_TIMESTAMP, 行内容有变化时更新 updata_time
+CREATE OR REPLACE FUNCTION set_updata_time() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW IS DISTINCT FROM OLD THEN
+        NEW.updata_time = CURRENT_TIMESTAMP AT TIME ZONE 'UTC';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_updata_time BEFORE UPDATE ON users
+    FOR EACH ROW EXECUTE PROCEDURE set_updata_time();
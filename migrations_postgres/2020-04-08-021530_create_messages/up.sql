@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+-- 消息表, message_type: 0 room, 1 p2p, 3 broadcast
+CREATE TABLE IF NOT EXISTS messages(
+    message_id BIGSERIAL PRIMARY KEY NOT NULL,
+    from_user INT NOT NULL REFERENCES users(user_id),
+    message_type SMALLINT NOT NULL,
+    room_name VARCHAR(50) NULL DEFAULT NULL,
+    to_user INT NULL DEFAULT NULL,
+    content TEXT NOT NULL,
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')
+);
+CREATE INDEX IF NOT EXISTS idx_room_time ON messages(room_name, create_time);
+CREATE INDEX IF NOT EXISTS idx_peer ON messages(from_user, to_user);
+
+-- 对应 MySQL 的 FULLTEXT ngram 索引, 三元组索引支持任意语言的 ILIKE 子串搜索
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX IF NOT EXISTS ft_content ON messages USING GIN (content gin_trgm_ops);
+
+-- 房间成员
+CREATE TABLE IF NOT EXISTS room_members(
+    room_name VARCHAR(50) NOT NULL,
+    user_id INT NOT NULL REFERENCES users(user_id),
+    join_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    PRIMARY KEY (room_name, user_id)
+);
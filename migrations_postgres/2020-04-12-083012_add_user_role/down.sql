@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users
+    DROP COLUMN role,
+    DROP COLUMN last_broadcast_id;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS audit_logs;
+DROP TABLE IF EXISTS sanctions;
+ALTER TABLE room_members
+    DROP COLUMN role;
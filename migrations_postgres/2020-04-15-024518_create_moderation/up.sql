@@ -0,0 +1,29 @@
+-- Your SQL goes here
+
+-- 房间内角色 role: 0 member, 1 manager, 2 owner
+ALTER TABLE room_members
+    ADD COLUMN role SMALLINT NOT NULL DEFAULT 0;
+
+-- 禁言/封禁, kind: 0 mute, 1 ban; room_name 为 NULL 时为全站范围, 全站封禁即停用账号
+-- expire_time 为 NULL 时永久有效
+CREATE TABLE IF NOT EXISTS sanctions(
+    sanction_id BIGSERIAL PRIMARY KEY NOT NULL,
+    room_name VARCHAR(50) NULL DEFAULT NULL,
+    user_id INT NOT NULL REFERENCES users(user_id),
+    kind SMALLINT NOT NULL,
+    expire_time TIMESTAMP NULL DEFAULT NULL,
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')
+);
+CREATE INDEX IF NOT EXISTS idx_user_kind ON sanctions(user_id, kind);
+
+-- 管理操作记录
+CREATE TABLE IF NOT EXISTS audit_logs(
+    log_id BIGSERIAL PRIMARY KEY NOT NULL,
+    operator INT NOT NULL REFERENCES users(user_id),
+    action VARCHAR(20) NOT NULL,
+    target_user INT NOT NULL REFERENCES users(user_id),
+    room_name VARCHAR(50) NULL DEFAULT NULL,
+    detail VARCHAR(255) NULL DEFAULT NULL,
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')
+);
+CREATE INDEX IF NOT EXISTS idx_target ON audit_logs(target_user, create_time);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- 内容过滤标记的消息, 供人工复核
+CREATE TABLE IF NOT EXISTS message_flags(
+    flag_id BIGSERIAL PRIMARY KEY NOT NULL,
+    message_id BIGINT NOT NULL REFERENCES messages(message_id),
+    filter_name VARCHAR(50) NOT NULL,
+    reason VARCHAR(255) NOT NULL,
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+-- 用户拉黑, blocker 不再收到 blocked 的单聊消息, 也看不到其在线状态
+CREATE TABLE IF NOT EXISTS user_blocks(
+    blocker INT NOT NULL REFERENCES users(user_id),
+    blocked INT NOT NULL REFERENCES users(user_id),
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    PRIMARY KEY (blocker, blocked)
+);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users
+    DROP COLUMN contacts_only;
+DROP TABLE IF EXISTS contacts;
+DROP TABLE IF EXISTS contact_requests;
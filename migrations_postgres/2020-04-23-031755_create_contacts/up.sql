@@ -0,0 +1,28 @@
+-- Your SQL goes here
+
+-- 好友申请 state: 0 pending, 1 accepted, 2 declined
+CREATE TABLE IF NOT EXISTS contact_requests(
+    request_id BIGSERIAL PRIMARY KEY NOT NULL,
+    from_user INT NOT NULL REFERENCES users(user_id),
+    to_user INT NOT NULL REFERENCES users(user_id),
+    state SMALLINT NOT NULL DEFAULT 0,
+    message VARCHAR(255) NULL DEFAULT NULL,
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    updata_time TIMESTAMP NULL DEFAULT NULL,
+    CONSTRAINT uk_from_to UNIQUE (from_user, to_user)
+);
+
+CREATE TRIGGER contact_requests_updata_time BEFORE UPDATE ON contact_requests
+    FOR EACH ROW EXECUTE PROCEDURE set_updata_time();
+
+-- 好友关系, 双向各存一条
+CREATE TABLE IF NOT EXISTS contacts(
+    user_id INT NOT NULL REFERENCES users(user_id),
+    contact_id INT NOT NULL REFERENCES users(user_id),
+    create_time TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    PRIMARY KEY (user_id, contact_id)
+);
+
+-- 只允许好友发起单聊
+ALTER TABLE users
+    ADD COLUMN contacts_only BOOLEAN NOT NULL DEFAULT FALSE;
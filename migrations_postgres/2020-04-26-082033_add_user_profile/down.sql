@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users
+    DROP CONSTRAINT uk_email,
+    DROP CONSTRAINT uk_phone,
+    DROP COLUMN display_name,
+    DROP COLUMN avatar,
+    DROP COLUMN email,
+    DROP COLUMN phone,
+    DROP COLUMN bio;
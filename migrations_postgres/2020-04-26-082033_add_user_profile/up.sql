@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+-- 个人资料, avatar 为头像附件地址
+ALTER TABLE users
+    ADD COLUMN display_name VARCHAR(50) NULL DEFAULT NULL,
+    ADD COLUMN avatar VARCHAR(255) NULL DEFAULT NULL,
+    ADD COLUMN email VARCHAR(100) NULL DEFAULT NULL,
+    ADD COLUMN phone VARCHAR(20) NULL DEFAULT NULL,
+    ADD COLUMN bio VARCHAR(255) NULL DEFAULT NULL,
+    ADD CONSTRAINT uk_email UNIQUE (email),
+    ADD CONSTRAINT uk_phone UNIQUE (phone);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS idx_purge_time;
+ALTER TABLE users
+    DROP COLUMN purged,
+    DROP COLUMN purge_time;
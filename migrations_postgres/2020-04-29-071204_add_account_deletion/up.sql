@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- delete_time 非空即账号已停用; purge_time 为注销后可恢复的截止时间, 停用时为空
+ALTER TABLE users
+    ADD COLUMN purge_time TIMESTAMP NULL DEFAULT NULL,
+    ADD COLUMN purged BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE INDEX IF NOT EXISTS idx_purge_time ON users(purge_time);
@@ -0,0 +1,111 @@
+use super::auth::Identity;
+use super::models::{fail, success_nodata, success_with_data};
+use crate::chat::server::{ChatServer, Moderate, Sanction};
+use crate::db::account::AccountConfig;
+use crate::db::{error::Error, Repository};
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct ConfirmForm {
+    passwd: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreForm {
+    user_name: String,
+    passwd: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeleteData {
+    // 此时间之前可以恢复
+    restore_before: NaiveDateTime,
+}
+
+// 停用和注销前需要再次确认密码
+async fn confirm(repo: &Repository, identity: &Identity, pd: String) -> Result<(), HttpResponse> {
+    let user_id = identity.user_id;
+    let r = repo
+        .store(move |s| {
+            let u = s.find_user(user_id)?;
+            s.verify_user(&u.user_name, &pd)
+        })
+        .await;
+    match r {
+        Ok(_) => Ok(()),
+        Err(Error::NotFound) => Err(fail("wrong password")),
+        Err(_) => Err(fail("verification failed")),
+    }
+}
+
+// 断开该用户的所有连接
+fn disconnect(srv: &Addr<ChatServer>, user_id: i32, notice: &str) {
+    srv.do_send(Moderate {
+        room: None,
+        user_id,
+        sanction: Sanction::Kick,
+        notice: notice.to_owned(),
+    });
+}
+
+// POST /api/users/me/deactivate
+pub async fn deactivate(
+    identity: Identity,
+    form: web::Json<ConfirmForm>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    if let Err(resp) = confirm(&repo, &identity, form.into_inner().passwd).await {
+        return resp;
+    }
+    let user_id = identity.user_id;
+    match repo.store(move |s| s.deactivate(user_id)).await {
+        Ok(_) => {
+            disconnect(&srv, identity.user_id, "account deactivated");
+            success_nodata("deactivate success")
+        }
+        Err(_) => fail("deactivate failed"),
+    }
+}
+
+// DELETE /api/users/me
+pub async fn delete(
+    identity: Identity,
+    form: web::Json<ConfirmForm>,
+    config: web::Data<AccountConfig>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    if let Err(resp) = confirm(&repo, &identity, form.into_inner().passwd).await {
+        return resp;
+    }
+    let (user_id, days) = (identity.user_id, config.restore_days);
+    match repo.store(move |s| s.delete_account(user_id, days)).await {
+        Ok(restore_before) => {
+            disconnect(&srv, identity.user_id, "account deleted");
+            success_with_data("delete success", DeleteData { restore_before })
+        }
+        Err(_) => fail("delete failed"),
+    }
+}
+
+// POST /api/users/restore, 恢复后需重新登录
+pub async fn restore(form: web::Json<RestoreForm>, repo: web::Data<Repository>) -> HttpResponse {
+    let RestoreForm { user_name, passwd } = form.into_inner();
+    let r = repo
+        .store(move |s| {
+            let u = s.find_restorable(&user_name, &passwd)?;
+            s.restore(u.user_id)
+        })
+        .await;
+    match r {
+        Ok(_) => success_nodata("restore success"),
+        Err(Error::NotFound) => fail("no restorable account"),
+        Err(_) => fail("restore failed"),
+    }
+}
@@ -0,0 +1,281 @@
+use super::auth::Admin;
+use super::models::{fail, success_nodata, success_with_data};
+use crate::chat::server::{
+    Announce, ChatServer, CloseSession, GetThroughput, ListRooms, ListSessions, Moderate,
+    OnlineUsers, RoomInfo, Sanction,
+};
+use crate::db::error::Error;
+use crate::db::user::{Role, UserSummary};
+use crate::db::{blocking, session, RedisPool, Repository};
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+const DEFAULT_USER_LIMIT: i64 = 50;
+const MAX_USER_LIMIT: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct AnnouncementForm {
+    content: String,
+}
+
+#[derive(Deserialize)]
+pub struct UserParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleForm {
+    role: Role,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RoomStats {
+    #[serde(flatten)]
+    live: RoomInfo,
+    // 存储中的房间成员数, 没有成员时为 null
+    members: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct AdminUser {
+    #[serde(flatten)]
+    user: UserSummary,
+    online: bool,
+}
+
+// 发布全站公告, 离线用户上线后补发
+pub async fn announce(
+    admin: Admin,
+    form: web::Json<AnnouncementForm>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let content = form.into_inner().content;
+    if content.trim().is_empty() {
+        return fail("empty content");
+    }
+    let r = srv
+        .send(Announce {
+            user_id: admin.0.user_id,
+            msg: content,
+        })
+        .await;
+    match r {
+        Ok(Ok(m)) => success_with_data("announce success", m),
+        _ => fail("announce failed"),
+    }
+}
+
+// 在线会话: 用户, 连接时间, 远端地址, 所在房间
+pub async fn sessions(_: Admin, srv: web::Data<Addr<ChatServer>>) -> HttpResponse {
+    match srv.send(ListSessions).await {
+        Ok(sessions) => success_with_data("query success", sessions),
+        Err(_) => fail("chat server unavailable"),
+    }
+}
+
+// 强制断开一个会话, 登录态不受影响, 客户端可以重连
+pub async fn close_session(
+    _: Admin,
+    path: web::Path<usize>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let r = srv
+        .send(CloseSession {
+            id: path.into_inner(),
+            reason: "disconnected by an administrator".to_owned(),
+        })
+        .await;
+    match r {
+        Ok(true) => success_nodata("session closed"),
+        Ok(false) => fail("session not found"),
+        Err(_) => fail("chat server unavailable"),
+    }
+}
+
+// 有会话的房间和数据库中有成员的房间, 按名称排序
+pub async fn rooms(
+    _: Admin,
+    srv: web::Data<Addr<ChatServer>>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let live = match srv.send(ListRooms).await {
+        Ok(live) => live,
+        Err(_) => return fail("chat server unavailable"),
+    };
+    let members = match repo.store(|s| s.room_members()).await {
+        Ok(members) => members,
+        Err(_) => return fail("query failed"),
+    };
+    let mut rooms: Vec<RoomStats> = live
+        .into_iter()
+        .map(|r| RoomStats {
+            members: members.iter().find(|(n, _)| *n == r.name).map(|(_, m)| *m),
+            live: r,
+        })
+        .collect();
+    for (name, count) in members {
+        if !rooms.iter().any(|r| r.live.name == name) {
+            rooms.push(RoomStats {
+                live: RoomInfo {
+                    name,
+                    sessions: 0,
+                    users: 0,
+                    messages: 0,
+                },
+                members: Some(count),
+            });
+        }
+    }
+    rooms.sort_by(|a, b| a.live.name.cmp(&b.live.name));
+    success_with_data("query success", rooms)
+}
+
+pub async fn throughput(_: Admin, srv: web::Data<Addr<ChatServer>>) -> HttpResponse {
+    match srv.send(GetThroughput).await {
+        Ok(stats) => success_with_data("query success", stats),
+        Err(_) => fail("chat server unavailable"),
+    }
+}
+
+pub async fn users(
+    _: Admin,
+    params: web::Query<UserParams>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_USER_LIMIT)
+        .clamp(1, MAX_USER_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let list = match repo.store(move |s| s.users(limit, offset)).await {
+        Ok(list) => list,
+        Err(_) => return fail("query failed"),
+    };
+    let ids = list.iter().map(|u| u.user_id).collect();
+    let online = srv
+        .send(OnlineUsers { users: ids })
+        .await
+        .unwrap_or_default();
+    let users: Vec<AdminUser> = list
+        .into_iter()
+        .map(|u| AdminUser {
+            online: online.contains(&u.user_id),
+            user: u,
+        })
+        .collect();
+    success_with_data("query success", users)
+}
+
+// 不能修改自己的角色, 避免没有管理员. 角色变化时断开该用户的连接,
+// 重新连接后按新角色处理, 降级的管理员不能继续广播
+pub async fn set_role(
+    admin: Admin,
+    path: web::Path<i32>,
+    form: web::Json<RoleForm>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let target = path.into_inner();
+    if target == admin.0.user_id {
+        return fail("cannot change your own role");
+    }
+    let role = form.role;
+    let before = repo
+        .store(move |s| {
+            let before = s.find_user(target)?.role();
+            s.set_role(target, role)?;
+            Ok(before)
+        })
+        .await;
+    match before {
+        Ok(before) if before != role => srv.do_send(Moderate {
+            room: None,
+            user_id: target,
+            sanction: Sanction::Kick,
+            notice: "role changed, please reconnect".to_owned(),
+        }),
+        Ok(_) => (),
+        Err(Error::NotFound) => return fail("user not found"),
+        Err(_) => return fail("set role failed"),
+    }
+    audit(
+        &repo,
+        admin.0.user_id,
+        "set_role",
+        target,
+        format!("{:?}", role),
+    )
+    .await;
+    success_nodata("role updated")
+}
+
+// 停用账号: 作废所有登录态并断开连接
+pub async fn deactivate_user(
+    admin: Admin,
+    path: web::Path<i32>,
+    repo: web::Data<Repository>,
+    redis: web::Data<RedisPool>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let target = path.into_inner();
+    if target == admin.0.user_id {
+        return fail("cannot deactivate yourself");
+    }
+    match repo.store(move |s| s.deactivate(target)).await {
+        Ok(_) => (),
+        Err(Error::NotFound) => return fail("user not found or already deactivated"),
+        Err(_) => return fail("deactivate failed"),
+    }
+    let redis = redis.get_ref().clone();
+    if let Err(e) = blocking(move || session::revoke_all(&redis, target)).await {
+        error!(user_id = target, error = ?e, "revoke sessions failed");
+    }
+    srv.do_send(Moderate {
+        room: None,
+        user_id: target,
+        sanction: Sanction::Kick,
+        notice: "account deactivated".to_owned(),
+    });
+    audit(&repo, admin.0.user_id, "deactivate", target, String::new()).await;
+    success_nodata("user deactivated")
+}
+
+// 恢复停用或注销(未清除)的账号
+pub async fn restore_user(
+    admin: Admin,
+    path: web::Path<i32>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let target = path.into_inner();
+    match repo.store(move |s| s.restore(target)).await {
+        Ok(_) => (),
+        Err(Error::NotFound) => return fail("user not found"),
+        Err(_) => return fail("restore failed"),
+    }
+    audit(&repo, admin.0.user_id, "restore", target, String::new()).await;
+    success_nodata("user restored")
+}
+
+// 写入审计日志, 失败时只记录错误
+async fn audit(
+    repo: &Repository,
+    operator: i32,
+    action: &'static str,
+    target: i32,
+    detail: String,
+) {
+    let r = repo
+        .store(move |s| {
+            let detail = Some(detail.as_str()).filter(|d| !d.is_empty());
+            s.audit(operator, action, target, None, detail)
+        })
+        .await;
+    if let Err(e) = r {
+        error!(error = ?e, "write audit log failed");
+    }
+}
@@ -0,0 +1,99 @@
+use crate::db::moderation::SanctionKind;
+use crate::db::{self, blocking, session, user::Role, RedisPool, Repository};
+use actix_web::{dev, error, http, web, Error, FromRequest, HttpRequest};
+use serde::Deserialize;
+use std::future::Future;
+use std::pin::Pin;
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+// 已登录的用户, token 取自 `Authorization: Bearer <token>` 或 `?token=<token>`
+// (浏览器的 WebSocket 无法设置请求头)
+pub struct Identity {
+    pub user_id: i32,
+    pub role: Role,
+}
+
+// 管理员, 非管理员返回 403
+pub struct Admin(pub Identity);
+
+fn token_of(req: &HttpRequest) -> Option<String> {
+    if let Some(v) = req.headers().get(http::header::AUTHORIZATION) {
+        if let Ok(v) = v.to_str() {
+            if let Some(token) = v.strip_prefix("Bearer ") {
+                return Some(token.trim().to_owned());
+            }
+        }
+    }
+    web::Query::<TokenQuery>::from_query(req.query_string())
+        .ok()
+        .map(|q| q.into_inner().token)
+}
+
+async fn identify(req: HttpRequest) -> Result<Identity, Error> {
+    let token = token_of(&req).ok_or_else(|| error::ErrorUnauthorized("missing token"))?;
+    let pool = req
+        .app_data::<web::Data<RedisPool>>()
+        .ok_or_else(|| error::ErrorInternalServerError("redis pool is not configured"))?
+        .get_ref()
+        .clone();
+    let repo = req
+        .app_data::<web::Data<Repository>>()
+        .ok_or_else(|| error::ErrorInternalServerError("repository is not configured"))?;
+    let user_id = match blocking(move || session::user_of(&pool, &token)).await {
+        Ok(user_id) => user_id,
+        Err(db::error::Error::NotFound) => return Err(error::ErrorUnauthorized("invalid token")),
+        Err(_) => return Err(error::ErrorServiceUnavailable("try again later")),
+    };
+    let u = match repo.store(move |s| s.find_user(user_id)).await {
+        Ok(u) => u,
+        Err(_) => return Err(error::ErrorUnauthorized("invalid token")),
+    };
+    match is_suspended(repo, user_id).await {
+        Ok(false) => (),
+        Ok(true) => return Err(error::ErrorForbidden("account suspended")),
+        // 查不到封禁状态时拒绝请求, 不能因为数据库故障解除封禁
+        Err(_) => return Err(error::ErrorServiceUnavailable("try again later")),
+    }
+    Ok(Identity {
+        user_id,
+        role: u.role(),
+    })
+}
+
+// 全站封禁的账号
+pub async fn is_suspended(repo: &Repository, user_id: i32) -> Result<bool, db::error::Error> {
+    repo.store(move |s| s.is_sanctioned(None, user_id, SanctionKind::Ban))
+        .await
+}
+
+impl FromRequest for Identity {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        Box::pin(identify(req.clone()))
+    }
+}
+
+impl FromRequest for Admin {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        let identity = identify(req.clone());
+        Box::pin(async move {
+            let identity = identity.await?;
+            if identity.role == Role::Admin {
+                Ok(Admin(identity))
+            } else {
+                Err(error::ErrorForbidden("admin only"))
+            }
+        })
+    }
+}
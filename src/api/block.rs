@@ -0,0 +1,52 @@
+use super::auth::Identity;
+use super::models::{fail, success_nodata, success_with_data};
+use crate::db::{error::Error, Repository};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockForm {
+    user_id: i32,
+}
+
+// 黑名单
+pub async fn list(identity: Identity, repo: web::Data<Repository>) -> Result<HttpResponse, Error> {
+    let user_id = identity.user_id;
+    let blocks = repo.store(move |s| s.blocks(user_id)).await?;
+    Ok(success_with_data("query success", blocks))
+}
+
+// 拉黑
+pub async fn add(
+    identity: Identity,
+    form: web::Json<BlockForm>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let (user_id, other) = (identity.user_id, form.user_id);
+    if other == user_id {
+        return fail("can not block yourself");
+    }
+    if let Err(Error::NotFound) = repo.store(move |s| s.find_user(other)).await {
+        return fail("user not found");
+    }
+    match repo.store(move |s| s.add_block(user_id, other)).await {
+        Ok(_) => success_nodata("block success"),
+        Err(Error::DuplicateData(_)) => fail("already blocked"),
+        Err(_) => fail("block failed"),
+    }
+}
+
+// 取消拉黑
+pub async fn remove(
+    identity: Identity,
+    path: web::Path<(i32,)>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let (user_id, other) = (identity.user_id, path.0);
+    match repo.store(move |s| s.remove_block(user_id, other)).await {
+        Ok(_) => success_nodata("unblock success"),
+        Err(Error::NotFound) => fail("not blocked"),
+        Err(_) => fail("unblock failed"),
+    }
+}
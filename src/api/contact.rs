@@ -0,0 +1,197 @@
+use super::auth::Identity;
+use super::models::{fail, success_nodata, success_with_data};
+use super::user::presence_of;
+use crate::chat::model::{ChatMessage, ChatMessageType};
+use crate::chat::server::{ChatServer, Notify};
+use crate::db::contact::{ACCEPTED, DECLINED};
+use crate::db::{error::Error, Repository, Tiny};
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestForm {
+    user_id: i32,
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Contact {
+    user_id: i32,
+    user_name: String,
+    online: bool,
+}
+
+fn notify(srv: &Addr<ChatServer>, user_id: i32, style: ChatMessageType, content: Option<String>) {
+    srv.do_send(Notify {
+        user_id,
+        msg: ChatMessage {
+            from: None,
+            style,
+            content,
+            message_id: None,
+        },
+    });
+}
+
+// 好友列表, 带在线状态
+pub async fn list(
+    identity: Identity,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let user_id = identity.user_id;
+    let r = repo.store(move |s| s.contacts(user_id)).await;
+    let ids = match r {
+        Ok(ids) => ids,
+        Err(_) => return fail("query failed"),
+    };
+    let query = ids.clone();
+    let names: HashMap<i32, String> = match repo.store(move |s| s.user_names(&query)).await {
+        Ok(names) => names.into_iter().collect(),
+        Err(_) => return fail("query failed"),
+    };
+    let presence = match presence_of(&repo, user_id, ids, &srv).await {
+        Ok(presence) => presence,
+        Err(_) => return fail("query failed"),
+    };
+    let contacts: Vec<Contact> = presence
+        .into_iter()
+        .filter_map(|p| {
+            names.get(&p.user_id).map(|name| Contact {
+                user_id: p.user_id,
+                user_name: name.to_owned(),
+                online: p.online,
+            })
+        })
+        .collect();
+    success_with_data("query success", contacts)
+}
+
+// 删除好友
+pub async fn remove(
+    identity: Identity,
+    path: web::Path<(i32,)>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let (user_id, other) = (identity.user_id, path.0);
+    match repo.store(move |s| s.remove_contact(user_id, other)).await {
+        Ok(_) => success_nodata("remove success"),
+        Err(Error::NotFound) => fail("not a contact"),
+        Err(_) => fail("remove failed"),
+    }
+}
+
+// 收到的待处理好友申请
+pub async fn requests(
+    identity: Identity,
+    repo: web::Data<Repository>,
+) -> Result<HttpResponse, Error> {
+    let user_id = identity.user_id;
+    let requests = repo
+        .store(move |s| s.pending_contact_requests(user_id))
+        .await?;
+    Ok(success_with_data("query success", requests))
+}
+
+// 发起好友申请, 对方在线时实时推送
+pub async fn send_request(
+    identity: Identity,
+    form: web::Json<RequestForm>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let RequestForm { user_id, message } = form.into_inner();
+    let from = identity.user_id;
+    if user_id == from {
+        return fail("can not add yourself");
+    }
+    match repo.store(move |s| s.find_user(user_id)).await {
+        Ok(_) => (),
+        Err(Error::NotFound) => return fail("user not found"),
+        Err(_) => return fail("request failed"),
+    }
+    match repo.store(move |s| s.are_contacts(from, user_id)).await {
+        Ok(false) => (),
+        Ok(true) => return fail("already contacts"),
+        Err(_) => return fail("request failed"),
+    }
+    // 被对方拉黑时不打扰对方
+    match repo.store(move |s| s.is_blocked(user_id, from)).await {
+        Ok(false) => (),
+        _ => return fail("request failed"),
+    }
+    let r = repo
+        .store(move |s| s.contact_request(from, user_id, message.as_deref()))
+        .await;
+    match r {
+        Ok(req) => {
+            notify(
+                &srv,
+                user_id,
+                ChatMessageType::ContactRequest(identity.user_id),
+                req.message.clone(),
+            );
+            success_with_data("request success", req)
+        }
+        Err(_) => fail("request failed"),
+    }
+}
+
+async fn answer(
+    identity: Identity,
+    request_id: i64,
+    state: Tiny,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let req = match repo
+        .store(move |s| s.find_contact_request(request_id))
+        .await
+    {
+        Ok(req) if req.to_user == identity.user_id => req,
+        Ok(_) | Err(Error::NotFound) => return fail("request not found"),
+        Err(_) => return fail("answer failed"),
+    };
+    match repo
+        .store(move |s| s.answer_contact_request(request_id, state))
+        .await
+    {
+        Ok(_) => {
+            if state == ACCEPTED {
+                notify(
+                    &srv,
+                    req.from_user,
+                    ChatMessageType::ContactAccepted(identity.user_id),
+                    None,
+                );
+            }
+            success_nodata("answer success")
+        }
+        Err(Error::NotFound) => fail("request already answered"),
+        Err(_) => fail("answer failed"),
+    }
+}
+
+// POST /api/contacts/requests/{request_id}/accept
+pub async fn accept(
+    identity: Identity,
+    path: web::Path<(i64,)>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    answer(identity, path.0, ACCEPTED, repo, srv).await
+}
+
+// POST /api/contacts/requests/{request_id}/decline
+pub async fn decline(
+    identity: Identity,
+    path: web::Path<(i64,)>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    answer(identity, path.0, DECLINED, repo, srv).await
+}
@@ -1,5 +1,69 @@
+use super::auth::Identity;
+use super::models::{fail, success_with_data};
+use crate::db::error::Error;
+use crate::db::search::{self, SearchEngine, SearchQuery};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpResponse};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::sync::Arc;
+
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    q: String,
+    room: Option<String>,
+    peer: Option<i32>,
+    sender: Option<i32>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
 // 添加消息
 async fn add_message() {}
 
 // 查询消息
 async fn query_message_since() {}
+
+// 全文搜索, 只返回调用者可见的消息
+pub async fn search(
+    identity: Identity,
+    params: web::Query<SearchParams>,
+    engine: web::Data<Arc<dyn SearchEngine>>,
+) -> Result<HttpResponse, Error> {
+    let params = params.into_inner();
+    if params.q.trim().is_empty() {
+        return Ok(fail("empty query"));
+    }
+    let terms = search::terms(&params.q);
+    if terms.is_empty() {
+        return Ok(fail("invalid query"));
+    }
+    let query = SearchQuery {
+        terms,
+        room: params.room,
+        peer: params.peer,
+        sender: params.sender,
+        since: params.since,
+        until: params.until,
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .clamp(1, MAX_SEARCH_LIMIT),
+        offset: params.offset.unwrap_or(0).max(0),
+    };
+    let engine = engine.get_ref().clone();
+    let reader = identity.user_id;
+    match web::block(move || engine.search(reader, &query)).await {
+        Ok(messages) => Ok(success_with_data("search success", messages)),
+        // MySQL 布尔模式的语法错误
+        Err(BlockingError::Error(Error::WapperError(ref e))) if e.contains("syntax error") => {
+            Ok(fail("invalid query"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
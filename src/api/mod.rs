@@ -1,5 +1,12 @@
+mod account;
+mod admin;
+pub mod auth;
+mod block;
+mod contact;
 mod message;
 mod models;
+mod moderation;
+mod password;
 mod room;
 pub mod route;
 mod service;
@@ -0,0 +1,302 @@
+use super::auth::Identity;
+use super::models::{fail, success_nodata, success_with_data};
+use crate::chat::server::{ChatServer, Moderate, Sanction};
+use crate::db::error::Error;
+use crate::db::moderation::{self, SanctionKind};
+use crate::db::room::RoomRole;
+use crate::db::storage::Storage;
+use crate::db::user::Role;
+use crate::db::Repository;
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, NaiveDateTime};
+use serde::Deserialize;
+use tracing::error;
+
+const DEFAULT_AUDIT_LIMIT: i64 = 50;
+const MAX_AUDIT_LIMIT: i64 = 200;
+// 限时处罚最长一年, 更长的用永久处罚
+const MAX_SANCTION_MINUTES: i64 = 366 * 24 * 60;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Mute,
+    Unmute,
+    Kick,
+    // 全站范围的封禁即停用账号
+    #[serde(alias = "suspend")]
+    Ban,
+    #[serde(alias = "unsuspend")]
+    Unban,
+}
+
+impl Action {
+    fn sanction(self) -> Sanction {
+        match self {
+            Action::Mute => Sanction::Mute,
+            Action::Unmute => Sanction::Unmute,
+            Action::Kick => Sanction::Kick,
+            Action::Ban => Sanction::Ban,
+            Action::Unban => Sanction::Unban,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::Mute => "mute",
+            Action::Unmute => "unmute",
+            Action::Kick => "kick",
+            Action::Ban => "ban",
+            Action::Unban => "unban",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationForm {
+    user_id: i32,
+    // mute/ban 的时长, 不传为永久
+    minutes: Option<i64>,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagerForm {
+    user_id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct AuditParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+// 全站管理员/版主可以处理角色比自己低的用户;
+// 房间内, 房主和房间管理员可以处理房间角色比自己低的普通用户
+fn permitted(
+    s: &dyn Storage,
+    operator: i32,
+    operator_role: Role,
+    target: i32,
+    room: Option<&str>,
+) -> Result<bool, Error> {
+    let target_role = s.find_user(target)?.role();
+    if operator_role >= Role::Moderator && target_role < operator_role {
+        return Ok(true);
+    }
+    let room = match room {
+        Some(room) => room,
+        None => return Ok(false),
+    };
+    if target_role >= Role::Moderator {
+        return Ok(false);
+    }
+    let operator_room_role = s.room_role(operator, room)?.unwrap_or(RoomRole::Member);
+    let target_room_role = s.room_role(target, room)?.unwrap_or(RoomRole::Member);
+    Ok(operator_room_role >= RoomRole::Manager && target_room_role < operator_room_role)
+}
+
+// 处罚记录在数据库, 踢出/封禁时从房间成员中移除
+async fn apply(
+    repo: &Repository,
+    room: Option<String>,
+    action: Action,
+    target: i32,
+    expire: Option<NaiveDateTime>,
+) -> Result<(), Error> {
+    repo.store(move |s| {
+        let name = room.as_deref();
+        match action {
+            Action::Mute => s.add_sanction(name, target, SanctionKind::Mute, expire)?,
+            Action::Unmute => s.remove_sanction(name, target, SanctionKind::Mute)?,
+            Action::Kick => (),
+            Action::Ban => s.add_sanction(name, target, SanctionKind::Ban, expire)?,
+            Action::Unban => s.remove_sanction(name, target, SanctionKind::Ban)?,
+        }
+        match (action, name) {
+            (Action::Kick, Some(room)) | (Action::Ban, Some(room)) => s.leave_room(target, room),
+            _ => Ok(()),
+        }
+    })
+    .await
+}
+
+fn notice_of(action: Action, form: &ModerationForm) -> String {
+    let mut notice = match action {
+        Action::Mute => format!("user {} has been muted", form.user_id),
+        Action::Unmute => format!("user {} has been unmuted", form.user_id),
+        Action::Kick => format!("user {} has been kicked", form.user_id),
+        Action::Ban => format!("user {} has been banned", form.user_id),
+        Action::Unban => format!("user {} has been unbanned", form.user_id),
+    };
+    if let (Action::Mute, Some(m)) | (Action::Ban, Some(m)) = (action, form.minutes) {
+        notice.push_str(&format!(" for {} minutes", m.max(1)));
+    }
+    if let Some(ref reason) = form.reason {
+        notice.push_str(": ");
+        notice.push_str(reason);
+    }
+    notice
+}
+
+async fn moderate(
+    identity: Identity,
+    room: Option<String>,
+    action: Action,
+    form: ModerationForm,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let (operator, operator_role) = (identity.user_id, identity.role);
+    let target = form.user_id;
+    let expire = match form.minutes {
+        Some(m) if m > MAX_SANCTION_MINUTES => return fail("invalid duration"),
+        Some(m) => Some(moderation::now() + Duration::minutes(m.max(1))),
+        None => None,
+    };
+    let room_name = room.clone();
+    let r = repo
+        .store(move |s| permitted(s, operator, operator_role, target, room_name.as_deref()))
+        .await;
+    match r {
+        Ok(true) => (),
+        Ok(false) => return fail("permission denied"),
+        Err(Error::NotFound) => return fail("user not found"),
+        Err(_) => return fail("moderation failed"),
+    }
+    if apply(&repo, room.clone(), action, target, expire)
+        .await
+        .is_err()
+    {
+        return fail("moderation failed");
+    }
+    let notice = notice_of(action, &form);
+    let (room_name, detail) = (room.clone(), notice.clone());
+    let r = repo
+        .store(move |s| {
+            s.audit(
+                operator,
+                action.name(),
+                target,
+                room_name.as_deref(),
+                Some(detail.as_str()),
+            )
+        })
+        .await;
+    if let Err(e) = r {
+        error!(error = ?e, "write audit log failed");
+    }
+    srv.do_send(Moderate {
+        room,
+        user_id: form.user_id,
+        sanction: action.sanction(),
+        notice,
+    });
+    success_nodata("moderation success")
+}
+
+// POST /api/rooms/{room}/{action}
+pub async fn moderate_room(
+    identity: Identity,
+    path: web::Path<(String, Action)>,
+    form: web::Json<ModerationForm>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let (room, action) = path.into_inner();
+    moderate(identity, Some(room), action, form.into_inner(), repo, srv).await
+}
+
+// POST /api/moderation/{action}, 全站范围
+pub async fn moderate_global(
+    identity: Identity,
+    path: web::Path<(Action,)>,
+    form: web::Json<ModerationForm>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let (action,) = path.into_inner();
+    moderate(identity, None, action, form.into_inner(), repo, srv).await
+}
+
+async fn set_manager(
+    identity: &Identity,
+    repo: &Repository,
+    room_name: String,
+    target: i32,
+    role: RoomRole,
+) -> HttpResponse {
+    let operator = identity.user_id;
+    let name = room_name.clone();
+    let is_owner = match repo.store(move |s| s.room_role(operator, &name)).await {
+        Ok(r) => r == Some(RoomRole::Owner),
+        Err(_) => return fail("set manager failed"),
+    };
+    if !is_owner && identity.role != Role::Admin {
+        return fail("permission denied");
+    }
+    let name = room_name.clone();
+    let r = repo
+        .store(move |s| s.set_room_role(target, &name, role))
+        .await;
+    match r {
+        Ok(_) => {
+            let action = if role == RoomRole::Manager {
+                "appoint"
+            } else {
+                "dismiss"
+            };
+            let r = repo
+                .store(move |s| s.audit(operator, action, target, Some(&room_name), None))
+                .await;
+            if let Err(e) = r {
+                error!(error = ?e, "write audit log failed");
+            }
+            success_nodata("set manager success")
+        }
+        Err(Error::NotFound) => fail("user is not a member of the room"),
+        Err(_) => fail("set manager failed"),
+    }
+}
+
+// POST /api/rooms/{room}/managers
+pub async fn add_manager(
+    identity: Identity,
+    path: web::Path<(String,)>,
+    form: web::Json<ManagerForm>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let (room,) = path.into_inner();
+    set_manager(&identity, &repo, room, form.user_id, RoomRole::Manager).await
+}
+
+// DELETE /api/rooms/{room}/managers/{user_id}
+pub async fn remove_manager(
+    identity: Identity,
+    path: web::Path<(String, i32)>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let (room, user_id) = path.into_inner();
+    set_manager(&identity, &repo, room, user_id, RoomRole::Member).await
+}
+
+// GET /api/moderation/audit
+pub async fn audit_logs(
+    identity: Identity,
+    params: web::Query<AuditParams>,
+    repo: web::Data<Repository>,
+) -> Result<HttpResponse, Error> {
+    if identity.role < Role::Moderator {
+        return Ok(fail("permission denied"));
+    }
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let logs = repo.store(move |s| s.audit_logs(limit, offset)).await?;
+    Ok(success_with_data("query success", logs))
+}
@@ -0,0 +1,172 @@
+use super::auth::Identity;
+use super::models::{fail, success_nodata};
+use crate::chat::server::{ChatServer, Moderate, Sanction};
+use crate::config::Config;
+use crate::db::{blocking, error::Error, session, RedisPool, Repository};
+use crate::mail::{Mail, Mailer};
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+const MAX_PASSWD_LEN: usize = 50;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeForm {
+    current_passwd: String,
+    new_passwd: String,
+}
+
+// 用户名或邮箱二选一
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgotForm {
+    user_name: Option<String>,
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetForm {
+    token: String,
+    passwd: String,
+}
+
+fn valid(passwd: &str) -> bool {
+    !passwd.is_empty() && passwd.chars().count() <= MAX_PASSWD_LEN
+}
+
+// 改密码后注销所有登录态并断开连接, 需要重新登录
+async fn sign_out(redis: &RedisPool, srv: &Addr<ChatServer>, user_id: i32) {
+    let redis = redis.clone();
+    if let Err(e) = blocking(move || session::revoke_all(&redis, user_id)).await {
+        error!(user_id, error = ?e, "revoke sessions failed");
+    }
+    srv.do_send(Moderate {
+        room: None,
+        user_id,
+        sanction: Sanction::Kick,
+        notice: "password changed, please log in again".to_owned(),
+    });
+}
+
+fn reset_mail(to: String, token: &str, reset_url: Option<&str>) -> Mail {
+    // `mail.password_reset_url` 为前端的重置页面, 不设置时只发送 token
+    let body = match reset_url {
+        Some(url) => format!(
+            "Open {}?token={} within 30 minutes to reset your password.",
+            url, token
+        ),
+        None => format!(
+            "Use this token within 30 minutes to reset your password: {}",
+            token
+        ),
+    };
+    Mail {
+        to,
+        subject: "Reset your password".to_owned(),
+        body,
+    }
+}
+
+// PUT /api/users/me/password
+pub async fn change(
+    identity: Identity,
+    form: web::Json<ChangeForm>,
+    repo: web::Data<Repository>,
+    redis: web::Data<RedisPool>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    if !valid(&form.new_passwd) {
+        return fail("invalid password");
+    }
+    if form.new_passwd == form.current_passwd {
+        return fail("new password is the same as the current one");
+    }
+    let user_id = identity.user_id;
+    let ChangeForm {
+        current_passwd,
+        new_passwd,
+    } = form.into_inner();
+    let r = repo
+        .store(move |s| s.change_passwd(user_id, &current_passwd, &new_passwd))
+        .await;
+    match r {
+        Ok(_) => {
+            sign_out(&redis, &srv, user_id).await;
+            success_nodata("change password success")
+        }
+        Err(Error::NotFound) => fail("wrong password"),
+        Err(_) => fail("change password failed"),
+    }
+}
+
+// POST /api/password/forgot, 用户是否存在都返回成功
+pub async fn forgot(
+    form: web::Json<ForgotForm>,
+    repo: web::Data<Repository>,
+    redis: web::Data<RedisPool>,
+    mailer: web::Data<Arc<dyn Mailer>>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let u = match form.into_inner() {
+        ForgotForm {
+            user_name: Some(name),
+            ..
+        } => repo.store(move |s| s.find_user_by_name(&name)).await,
+        ForgotForm {
+            email: Some(email), ..
+        } => repo.store(move |s| s.find_user_by_email(&email)).await,
+        _ => return fail("user name or email required"),
+    };
+    let u = match u {
+        Ok(u) => u,
+        Err(Error::NotFound) => return success_nodata("reset mail sent"),
+        Err(_) => return fail("request failed"),
+    };
+    let to = match u.email {
+        Some(email) => email,
+        None => return success_nodata("reset mail sent"),
+    };
+    let (redis, user_id) = (redis.get_ref().clone(), u.user_id);
+    let token = match blocking(move || session::create_reset(&redis, user_id)).await {
+        Ok(token) => token,
+        Err(_) => return fail("request failed"),
+    };
+    let mail = reset_mail(to, &token, config.mail.password_reset_url.as_deref());
+    let mailer = mailer.get_ref().clone();
+    match web::block(move || mailer.send(&mail)).await {
+        Ok(_) => success_nodata("reset mail sent"),
+        Err(e) => {
+            error!(error = %e, "send reset mail failed");
+            fail("request failed")
+        }
+    }
+}
+
+// POST /api/password/reset
+pub async fn reset(
+    form: web::Json<ResetForm>,
+    repo: web::Data<Repository>,
+    redis: web::Data<RedisPool>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    if !valid(&form.passwd) {
+        return fail("invalid password");
+    }
+    let (pool, token) = (redis.get_ref().clone(), form.token.clone());
+    let user_id = match blocking(move || session::take_reset(&pool, &token)).await {
+        Ok(user_id) => user_id,
+        Err(Error::NotFound) => return fail("invalid or expired token"),
+        Err(_) => return fail("reset failed"),
+    };
+    let passwd = form.into_inner().passwd;
+    match repo.store(move |s| s.set_passwd(user_id, &passwd)).await {
+        Ok(_) => {
+            sign_out(&redis, &srv, user_id).await;
+            success_nodata("reset success")
+        }
+        Err(_) => fail("reset failed"),
+    }
+}
@@ -1,3 +1,6 @@
+use super::{account, admin, block, contact, message, moderation, password, user};
+use crate::config::Features;
+use crate::cors;
 use actix_web::middleware::errhandlers::ErrorHandlerResponse;
 use actix_web::{dev, http, web, HttpResponse, Result};
 
@@ -15,3 +18,89 @@ pub fn write_400<B>(mut res: dev::ServiceResponse<B>) -> Result<ErrorHandlerResp
         .insert(http::header::WARNING, s);
     Ok(ErrorHandlerResponse::Response(res))
 }
+
+// 关闭的功能不注册路由
+pub fn config(cfg: &mut web::ServiceConfig, features: &Features) {
+    let mut scope = web::scope("/api");
+    if features.registration {
+        scope = scope.route("/signup", web::post().to(user::signup));
+    }
+    if features.password_reset {
+        scope = scope
+            .route("/password/forgot", web::post().to(password::forgot))
+            .route("/password/reset", web::post().to(password::reset));
+    }
+    if features.search {
+        scope = scope.route("/search", web::get().to(message::search));
+    }
+    cfg.service(
+        scope
+            .route("/login", web::post().to(user::login))
+            .route("/users/me/password", web::put().to(password::change))
+            .route("/users/restore", web::post().to(account::restore))
+            .route("/users/me", web::delete().to(account::delete))
+            .route("/users/me/deactivate", web::post().to(account::deactivate))
+            .route("/users/presence", web::get().to(user::presence))
+            .route("/users/settings", web::put().to(user::settings))
+            .route("/users/me/profile", web::get().to(user::profile))
+            .route("/users/me/profile", web::put().to(user::update_profile))
+            .route(
+                "/users/{user_id}/profile",
+                web::get().to(user::public_profile),
+            )
+            .route("/contacts", web::get().to(contact::list))
+            .route("/contacts/requests", web::get().to(contact::requests))
+            .route("/contacts/requests", web::post().to(contact::send_request))
+            .route(
+                "/contacts/requests/{request_id}/accept",
+                web::post().to(contact::accept),
+            )
+            .route(
+                "/contacts/requests/{request_id}/decline",
+                web::post().to(contact::decline),
+            )
+            .route("/contacts/{user_id}", web::delete().to(contact::remove))
+            .route("/blocks", web::get().to(block::list))
+            .route("/blocks", web::post().to(block::add))
+            .route("/blocks/{user_id}", web::delete().to(block::remove))
+            .route("/admin/announcements", web::post().to(admin::announce))
+            .route("/admin/sessions", web::get().to(admin::sessions))
+            .route(
+                "/admin/sessions/{session_id}",
+                web::delete().to(admin::close_session),
+            )
+            .route("/admin/rooms", web::get().to(admin::rooms))
+            .route("/admin/throughput", web::get().to(admin::throughput))
+            .route("/admin/users", web::get().to(admin::users))
+            .route(
+                "/admin/users/{user_id}/role",
+                web::put().to(admin::set_role),
+            )
+            .route(
+                "/admin/users/{user_id}/deactivate",
+                web::post().to(admin::deactivate_user),
+            )
+            .route(
+                "/admin/users/{user_id}/restore",
+                web::post().to(admin::restore_user),
+            )
+            .route("/moderation/audit", web::get().to(moderation::audit_logs))
+            .route(
+                "/moderation/{action}",
+                web::post().to(moderation::moderate_global),
+            )
+            .route(
+                "/rooms/{room}/managers",
+                web::post().to(moderation::add_manager),
+            )
+            .route(
+                "/rooms/{room}/managers/{user_id}",
+                web::delete().to(moderation::remove_manager),
+            )
+            .route(
+                "/rooms/{room}/{action}",
+                web::post().to(moderation::moderate_room),
+            )
+            .wrap_fn(cors::handle),
+    );
+}
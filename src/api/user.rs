@@ -1,5 +1,288 @@
+use super::auth::{is_suspended, Identity};
+use super::models::{fail, success_nodata, success_with_data};
+use crate::chat::server::{ChatServer, OnlineUsers};
+use crate::db::{blocking, error::Error, session, user::ProfileChangeset, RedisPool, Repository};
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserForm {
+    user_name: String,
+    passwd: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginData {
+    user_id: i32,
+    token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsForm {
+    contacts_only: bool,
+}
+
+// 不传的字段不修改, 空字符串清空
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileForm {
+    display_name: Option<String>,
+    avatar: Option<String>,
+    email: Option<String>,
+    phone: Option<String>,
+    bio: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicProfile {
+    user_id: i32,
+    user_name: String,
+    display_name: Option<String>,
+    avatar: Option<String>,
+    bio: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PresenceParams {
+    // 逗号分隔的用户 id
+    ids: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Presence {
+    pub user_id: i32,
+    pub online: bool,
+}
+
+// 查询在线状态, 被调用者拉黑的用户总是显示为离线
+pub async fn presence_of(
+    repo: &Repository,
+    viewer: i32,
+    ids: Vec<i32>,
+    srv: &Addr<ChatServer>,
+) -> Result<Vec<Presence>, Error> {
+    let blocked: HashSet<i32> = repo
+        .store(move |s| s.blocks(viewer))
+        .await?
+        .into_iter()
+        .map(|b| b.blocked)
+        .collect();
+    let visible: Vec<i32> = ids
+        .iter()
+        .cloned()
+        .filter(|id| !blocked.contains(id))
+        .collect();
+    let online = srv
+        .send(OnlineUsers { users: visible })
+        .await
+        .map_err(|e| Error::WapperError(e.to_string()))?;
+    Ok(ids
+        .into_iter()
+        .map(|user_id| Presence {
+            user_id,
+            online: online.contains(&user_id),
+        })
+        .collect())
+}
+
+fn change(v: Option<String>) -> Option<Option<String>> {
+    v.map(|s| {
+        let s = s.trim();
+        if s.is_empty() {
+            None
+        } else {
+            Some(s.to_owned())
+        }
+    })
+}
+
+fn too_long(v: &Option<Option<String>>, max: usize) -> bool {
+    match v {
+        Some(Some(s)) => s.chars().count() > max,
+        _ => false,
+    }
+}
+
+fn validate(changes: &ProfileChangeset) -> Result<(), &'static str> {
+    if too_long(&changes.display_name, 50) {
+        return Err("display name too long");
+    }
+    if too_long(&changes.avatar, 255) {
+        return Err("avatar too long");
+    }
+    if too_long(&changes.bio, 255) {
+        return Err("bio too long");
+    }
+    if let Some(Some(ref email)) = changes.email {
+        let valid = email.chars().count() <= 100
+            && email
+                .split_once('@')
+                .is_some_and(|(name, domain)| !name.is_empty() && domain.contains('.'));
+        if !valid {
+            return Err("invalid email");
+        }
+    }
+    if let Some(Some(ref phone)) = changes.phone {
+        let digits = phone.strip_prefix('+').unwrap_or(phone);
+        let valid = (5..=19).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_digit());
+        if !valid {
+            return Err("invalid phone");
+        }
+    }
+    Ok(())
+}
+
 // 注册
-async fn signup() {}
+pub async fn signup(form: web::Json<UserForm>, repo: web::Data<Repository>) -> HttpResponse {
+    let UserForm { user_name, passwd } = form.into_inner();
+    match repo.store(move |s| s.add_user(user_name, passwd)).await {
+        Ok(_) => success_nodata("signup success"),
+        Err(Error::DuplicateData(_)) => fail("user name already exists"),
+        Err(_) => fail("signup failed"),
+    }
+}
 
 // 登录
-async fn login() {}
+pub async fn login(
+    form: web::Json<UserForm>,
+    repo: web::Data<Repository>,
+    redis: web::Data<RedisPool>,
+) -> HttpResponse {
+    let UserForm { user_name, passwd } = form.into_inner();
+    let r = repo
+        .store(move |s| s.verify_user(&user_name, &passwd))
+        .await;
+    let u = match r {
+        Ok(u) => u,
+        Err(Error::NotFound) => return fail("wrong user name or password"),
+        Err(_) => return fail("login failed"),
+    };
+    match is_suspended(&repo, u.user_id).await {
+        Ok(false) => (),
+        Ok(true) => return fail("account suspended"),
+        Err(_) => return fail("login failed"),
+    }
+    let (redis, user_id) = (redis.get_ref().clone(), u.user_id);
+    match blocking(move || session::create(&redis, user_id)).await {
+        Ok(token) => success_with_data(
+            "login success",
+            LoginData {
+                user_id: u.user_id,
+                token,
+            },
+        ),
+        Err(_) => fail("login failed"),
+    }
+}
+
+// GET /api/users/presence?ids=1,2,3
+pub async fn presence(
+    identity: Identity,
+    params: web::Query<PresenceParams>,
+    repo: web::Data<Repository>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    let ids: Result<Vec<i32>, _> = params
+        .ids
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.trim().parse::<i32>())
+        .collect();
+    let ids = match ids {
+        Ok(ids) => ids,
+        Err(_) => return fail("invalid ids"),
+    };
+    match presence_of(&repo, identity.user_id, ids, &srv).await {
+        Ok(presence) => success_with_data("query success", presence),
+        Err(_) => fail("query failed"),
+    }
+}
+
+// PUT /api/users/settings
+pub async fn settings(
+    identity: Identity,
+    form: web::Json<SettingsForm>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let (user_id, only) = (identity.user_id, form.contacts_only);
+    match repo
+        .store(move |s| s.set_contacts_only(user_id, only))
+        .await
+    {
+        Ok(_) => success_nodata("update success"),
+        Err(_) => fail("update failed"),
+    }
+}
+
+// GET /api/users/me/profile
+pub async fn profile(
+    identity: Identity,
+    repo: web::Data<Repository>,
+) -> Result<HttpResponse, Error> {
+    let user_id = identity.user_id;
+    let profile = repo.store(move |s| s.profile(user_id)).await?;
+    Ok(success_with_data("query success", profile))
+}
+
+// PUT /api/users/me/profile
+pub async fn update_profile(
+    identity: Identity,
+    form: web::Json<ProfileForm>,
+    repo: web::Data<Repository>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let changes = ProfileChangeset {
+        display_name: change(form.display_name),
+        avatar: change(form.avatar),
+        email: change(form.email),
+        phone: change(form.phone),
+        bio: change(form.bio),
+    };
+    if changes.display_name.is_none()
+        && changes.avatar.is_none()
+        && changes.email.is_none()
+        && changes.phone.is_none()
+        && changes.bio.is_none()
+    {
+        return fail("nothing to update");
+    }
+    if let Err(reason) = validate(&changes) {
+        return fail(reason);
+    }
+    let user_id = identity.user_id;
+    match repo
+        .store(move |s| s.update_profile(user_id, &changes))
+        .await
+    {
+        Ok(_) => success_nodata("update success"),
+        Err(Error::DuplicateData(field)) => fail(&format!("{} already in use", field)),
+        Err(_) => fail("update failed"),
+    }
+}
+
+// GET /api/users/{user_id}/profile, 不包含邮箱和手机号
+pub async fn public_profile(
+    _: Identity,
+    path: web::Path<(i32,)>,
+    repo: web::Data<Repository>,
+) -> Result<HttpResponse, Error> {
+    let (user_id,) = path.into_inner();
+    let p = repo.store(move |s| s.profile(user_id)).await?;
+    Ok(success_with_data(
+        "query success",
+        PublicProfile {
+            user_id: p.user_id,
+            user_name: p.user_name,
+            display_name: p.display_name,
+            avatar: p.avatar,
+            bio: p.bio,
+        },
+    ))
+}
@@ -0,0 +1,465 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Clone, Copy)]
+pub enum Target<'a> {
+    Room(&'a str),
+    User,
+    Broadcast,
+}
+
+// 经过 ChatServer 的消息, 在分发之前
+pub struct Outgoing<'a> {
+    pub user_id: i32,
+    pub target: Target<'a>,
+    pub content: &'a str,
+}
+
+pub enum Verdict {
+    Pass,
+    // 替换内容, 后面的过滤器看到替换后的内容
+    Redact(String),
+    // 丢弃消息并告知发送者原因
+    Reject(String),
+    // 照常投递, 但记录下来供审核
+    Flag(String),
+}
+
+// 过滤链的一环, 实现它并通过 `FilterChain::with` 加入自定义检查
+// 过滤链在聊天服务器自己的线程上运行, 因此要求 `Send`
+pub trait MessageFilter: Send {
+    fn name(&self) -> &str;
+    fn check(&self, msg: &Outgoing) -> Verdict;
+}
+
+pub struct Flag {
+    pub filter: String,
+    pub reason: String,
+}
+
+pub struct Filtered {
+    pub content: String,
+    pub flags: Vec<Flag>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkPolicy {
+    Allow,
+    Flag,
+    Block,
+}
+
+// 配置文件中为 snake_case, `FILTER_CONFIG` 的 json 文件中为 camelCase
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    // content 的最大字符数, `websocket.max_content_length` 没有设置的类型使用
+    #[serde(alias = "maxLength")]
+    pub max_length: usize,
+    #[serde(alias = "blockedWords")]
+    pub blocked_words: Vec<String>,
+    // 按房间追加的敏感词
+    #[serde(alias = "roomBlockedWords")]
+    pub room_blocked_words: HashMap<String, Vec<String>>,
+    // 拒绝含敏感词的消息, 而不是打码
+    #[serde(alias = "rejectBlockedWords")]
+    pub reject_blocked_words: bool,
+    pub links: LinkPolicy,
+    // 不受链接策略限制的域名 (含子域名)
+    #[serde(alias = "allowedDomains")]
+    pub allowed_domains: Vec<String>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> FilterConfig {
+        FilterConfig {
+            max_length: 4096,
+            blocked_words: Vec::new(),
+            room_blocked_words: HashMap::new(),
+            reject_blocked_words: false,
+            links: LinkPolicy::Allow,
+            allowed_domains: Vec::new(),
+        }
+    }
+}
+
+impl FilterConfig {
+    // `FILTER_CONFIG` 指向的 json 文件
+    pub fn from_file(path: &str) -> Result<FilterConfig, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+// `content` 超过 `max_length` 个字符时返回拒绝原因, 未超过时为 None
+pub fn too_long(content: &str, max_length: usize) -> Option<String> {
+    if content.chars().count() > max_length {
+        Some(format!("message longer than {} characters", max_length))
+    } else {
+        None
+    }
+}
+
+pub struct Blocklist {
+    words: Vec<String>,
+    rooms: HashMap<String, Vec<String>>,
+    reject: bool,
+}
+
+impl Blocklist {
+    pub fn new(words: Vec<String>, rooms: HashMap<String, Vec<String>>, reject: bool) -> Self {
+        let lower = |v: Vec<String>| -> Vec<String> {
+            v.into_iter()
+                .filter(|w| !w.is_empty())
+                .map(|w| w.to_lowercase())
+                .collect()
+        };
+        Blocklist {
+            words: lower(words),
+            rooms: rooms.into_iter().map(|(k, v)| (k, lower(v))).collect(),
+            reject,
+        }
+    }
+}
+
+// 按字符转小写; `origin[i]` 为小写文本第 i 个字节所属字符在原文中的偏移,
+// 用来把匹配位置映射回原文 (非 ascii 字符转小写后字节数可能变化)
+fn fold(text: &str) -> (String, Vec<usize>) {
+    let mut lower = String::with_capacity(text.len());
+    let mut origin = Vec::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        for l in c.to_lowercase() {
+            lower.push(l);
+            origin.extend(std::iter::repeat_n(i, l.len_utf8()));
+        }
+    }
+    (lower, origin)
+}
+
+// 以 ascii 字母或数字开头/结尾的词只在词边界处匹配, 避免误伤包含它的正常单词
+// (如 "class" 中的 "ass"); 中文等不以空格分词的文字按子串匹配
+fn at_boundary(text: &str, start: usize, end: usize, word: &str) -> bool {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric());
+    let first = word
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric());
+    let last = word
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_ascii_alphanumeric());
+    let inside = first && is_word(text[..start].chars().next_back())
+        || last && is_word(text[end..].chars().next());
+    !inside
+}
+
+impl MessageFilter for Blocklist {
+    fn name(&self) -> &str {
+        "blocklist"
+    }
+
+    fn check(&self, msg: &Outgoing) -> Verdict {
+        let room_words = match msg.target {
+            Target::Room(room) => self.rooms.get(room),
+            _ => None,
+        };
+        let text = msg.content;
+        let (lower, origin) = fold(text);
+        let mut hits: Vec<(usize, usize)> = Vec::new();
+        for word in self.words.iter().chain(room_words.into_iter().flatten()) {
+            for (i, _) in lower.match_indices(word.as_str()) {
+                // 匹配的最后一个字节所属的原文字符, 到它的结尾为止
+                let last = origin[i + word.len() - 1];
+                let end = last + text[last..].chars().next().map_or(0, |c| c.len_utf8());
+                let start = origin[i];
+                if at_boundary(text, start, end, word) {
+                    hits.push((start, end));
+                }
+            }
+        }
+        if hits.is_empty() {
+            return Verdict::Pass;
+        }
+        if self.reject {
+            return Verdict::Reject("message contains blocked words".to_owned());
+        }
+
+        hits.sort();
+        let mut redacted = String::with_capacity(text.len());
+        let mut pos = 0;
+        for (start, end) in hits {
+            if end <= pos {
+                continue;
+            }
+            let start = start.max(pos);
+            redacted.push_str(&text[pos..start]);
+            redacted.extend(std::iter::repeat_n('*', text[start..end].chars().count()));
+            pos = end;
+        }
+        redacted.push_str(&text[pos..]);
+        Verdict::Redact(redacted)
+    }
+}
+
+pub struct LinkFilter {
+    policy: LinkPolicy,
+    allowed_domains: Vec<String>,
+}
+
+impl LinkFilter {
+    pub fn new(policy: LinkPolicy, allowed_domains: Vec<String>) -> Self {
+        LinkFilter {
+            policy,
+            allowed_domains: allowed_domains
+                .into_iter()
+                .map(|d| d.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    fn host_of(word: &str) -> Option<&str> {
+        let rest = if let Some(rest) = word.strip_prefix("http://") {
+            rest
+        } else if let Some(rest) = word.strip_prefix("https://") {
+            rest
+        } else if word.starts_with("www.") {
+            word
+        } else {
+            return None;
+        };
+        rest.split(['/', '?', '#', ':'])
+            .next()
+            .filter(|h| !h.is_empty())
+    }
+
+    fn allowed(&self, host: &str) -> bool {
+        self.allowed_domains
+            .iter()
+            .any(|d| host == d || host.ends_with(&format!(".{}", d)))
+    }
+}
+
+impl MessageFilter for LinkFilter {
+    fn name(&self) -> &str {
+        "links"
+    }
+
+    fn check(&self, msg: &Outgoing) -> Verdict {
+        let lower = msg.content.to_ascii_lowercase();
+        let link = lower
+            .split_whitespace()
+            .filter_map(LinkFilter::host_of)
+            .find(|host| !self.allowed(host));
+        match (link, self.policy) {
+            (Some(host), LinkPolicy::Block) => {
+                Verdict::Reject(format!("links to {} are not allowed", host))
+            }
+            (Some(host), LinkPolicy::Flag) => Verdict::Flag(format!("link to {}", host)),
+            _ => Verdict::Pass,
+        }
+    }
+}
+
+pub struct FilterChain {
+    filters: Vec<Box<dyn MessageFilter>>,
+}
+
+impl FilterChain {
+    pub fn from_config(config: FilterConfig) -> FilterChain {
+        let mut chain = FilterChain {
+            filters: Vec::new(),
+        };
+        if config.links != LinkPolicy::Allow {
+            chain = chain.with(Box::new(LinkFilter::new(
+                config.links,
+                config.allowed_domains,
+            )));
+        }
+        if !config.blocked_words.is_empty() || !config.room_blocked_words.is_empty() {
+            chain = chain.with(Box::new(Blocklist::new(
+                config.blocked_words,
+                config.room_blocked_words,
+                config.reject_blocked_words,
+            )));
+        }
+        chain
+    }
+
+    // 过滤器按加入的顺序执行
+    pub fn with(mut self, filter: Box<dyn MessageFilter>) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    // 依次执行每个过滤器, `Err` 为第一个拒绝的原因
+    pub fn run(&self, msg: &Outgoing) -> Result<Filtered, String> {
+        let mut content = msg.content.to_owned();
+        let mut flags = Vec::new();
+        for filter in &self.filters {
+            let current = Outgoing {
+                user_id: msg.user_id,
+                target: msg.target,
+                content: &content,
+            };
+            match filter.check(&current) {
+                Verdict::Pass => (),
+                Verdict::Redact(redacted) => content = redacted,
+                Verdict::Reject(reason) => return Err(reason),
+                Verdict::Flag(reason) => flags.push(Flag {
+                    filter: filter.name().to_owned(),
+                    reason,
+                }),
+            }
+        }
+        Ok(Filtered { content, flags })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(filter: &dyn MessageFilter, target: Target, content: &str) -> Verdict {
+        filter.check(&Outgoing {
+            user_id: 1,
+            target,
+            content,
+        })
+    }
+
+    fn redacted(filter: &dyn MessageFilter, content: &str) -> Option<String> {
+        match check(filter, Target::Broadcast, content) {
+            Verdict::Redact(text) => Some(text),
+            Verdict::Pass => None,
+            _ => panic!("unexpected verdict"),
+        }
+    }
+
+    fn blocklist(words: &[&str]) -> Blocklist {
+        let words = words.iter().map(|w| w.to_string()).collect();
+        Blocklist::new(words, HashMap::new(), false)
+    }
+
+    #[test]
+    fn blocklist_redacts_case_insensitively() {
+        let f = blocklist(&["darn"]);
+        assert_eq!(redacted(&f, "darn it").as_deref(), Some("**** it"));
+        assert_eq!(
+            redacted(&f, "DARN it, Darn!").as_deref(),
+            Some("**** it, ****!")
+        );
+        assert_eq!(redacted(&f, "fine"), None);
+    }
+
+    #[test]
+    fn blocklist_matches_multibyte_words() {
+        let f = blocklist(&["傻瓜", "ärger"]);
+        assert_eq!(redacted(&f, "你是傻瓜吗").as_deref(), Some("你是**吗"));
+        assert_eq!(
+            redacted(&f, "so ein ÄRGER.").as_deref(),
+            Some("so ein *****.")
+        );
+        // 转小写后字节数变化的字符, 替换的仍是原文的字符
+        let f = blocklist(&["i̇stanbul"]);
+        assert_eq!(redacted(&f, "İSTANBUL!").as_deref(), Some("********!"));
+    }
+
+    #[test]
+    fn blocklist_keeps_words_containing_a_blocked_word() {
+        let f = blocklist(&["ass"]);
+        assert_eq!(redacted(&f, "first class passage"), None);
+        assert_eq!(redacted(&f, "Ass-hat").as_deref(), Some("***-hat"));
+        assert_eq!(redacted(&f, "(ass)").as_deref(), Some("(***)"));
+    }
+
+    #[test]
+    fn blocklist_merges_overlapping_hits() {
+        let f = blocklist(&["bad word", "word"]);
+        assert_eq!(redacted(&f, "a bad word").as_deref(), Some("a ********"));
+    }
+
+    #[test]
+    fn blocklist_room_words_and_reject() {
+        let mut rooms = HashMap::new();
+        rooms.insert("kids".to_owned(), vec!["heck".to_owned()]);
+        let f = Blocklist::new(Vec::new(), rooms, true);
+        assert!(matches!(
+            check(&f, Target::Room("kids"), "what the heck"),
+            Verdict::Reject(_)
+        ));
+        assert!(matches!(
+            check(&f, Target::Room("adults"), "what the heck"),
+            Verdict::Pass
+        ));
+    }
+
+    #[test]
+    fn links_are_detected() {
+        let f = LinkFilter::new(LinkPolicy::Block, Vec::new());
+        for text in &[
+            "see http://evil.com/x",
+            "see HTTPS://Evil.com",
+            "www.evil.com please",
+        ] {
+            match check(&f, Target::Broadcast, text) {
+                Verdict::Reject(reason) => assert!(reason.contains("evil.com"), "{}", reason),
+                _ => panic!("link not blocked: {}", text),
+            }
+        }
+        assert!(matches!(
+            check(&f, Target::Broadcast, "no links here, just evil.com"),
+            Verdict::Pass
+        ));
+    }
+
+    #[test]
+    fn allowed_domains_include_subdomains_only() {
+        let f = LinkFilter::new(LinkPolicy::Flag, vec!["Example.com".to_owned()]);
+        for text in &["https://example.com/a", "http://docs.example.com:8080?q"] {
+            assert!(matches!(check(&f, Target::Broadcast, text), Verdict::Pass));
+        }
+        match check(&f, Target::Broadcast, "https://badexample.com") {
+            Verdict::Flag(reason) => assert_eq!(reason, "link to badexample.com"),
+            _ => panic!("link not flagged"),
+        }
+    }
+
+    #[test]
+    fn max_length_counts_chars() {
+        assert_eq!(too_long("", 3), None);
+        assert_eq!(too_long("abc", 3), None);
+        assert_eq!(
+            too_long("abcd", 3).as_deref(),
+            Some("message longer than 3 characters")
+        );
+        // 6 和 12 字节, 都是 3 个字符
+        assert_eq!(too_long("éèê", 3), None);
+        assert_eq!(too_long("😀😀😀", 3), None);
+        assert!(too_long("😀😀😀😀", 3).is_some());
+        // 组合重音符本身算一个字符
+        assert!(too_long("e\u{301}", 1).is_some());
+    }
+
+    #[test]
+    fn chain_applies_redactions_in_order() {
+        let config = FilterConfig {
+            blocked_words: vec!["darn".to_owned()],
+            links: LinkPolicy::Flag,
+            ..FilterConfig::default()
+        };
+        let chain = FilterChain::from_config(config);
+        let out = chain
+            .run(&Outgoing {
+                user_id: 1,
+                target: Target::User,
+                content: "darn, see www.a.com",
+            })
+            .ok()
+            .unwrap();
+        assert_eq!(out.content, "****, see www.a.com");
+        assert_eq!(out.flags.len(), 1);
+        assert_eq!(out.flags[0].filter, "links");
+    }
+}
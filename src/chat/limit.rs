@@ -0,0 +1,426 @@
+use super::model::ChatMessageType;
+use crate::db::RedisPool;
+use crate::metrics::METRICS;
+use actix_web::web;
+use r2d2_redis::redis::{self, Script};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Bucket {
+    pub capacity: f64,
+    // 每秒回复的令牌数
+    pub refill: f64,
+}
+
+// 空桶回满的最长时间, 也是 redis 中令牌桶的过期时间上限
+pub const MAX_REFILL_TIME: Duration = Duration::from_secs(7 * 24 * 3600);
+
+impl Bucket {
+    // `<容量>:<每秒补充数>`, 格式同 `RATE_LIMIT_<NAME>`
+    pub fn parse(value: &str) -> Option<Bucket> {
+        let mut parts = value.splitn(2, ':');
+        let capacity = parts.next()?.trim().parse::<f64>().ok()?;
+        let refill = parts.next()?.trim().parse::<f64>().ok()?;
+        Some(Bucket { capacity, refill })
+    }
+
+    // 有限值, 且 `MAX_REFILL_TIME` 内能回满
+    pub fn is_valid(&self) -> bool {
+        self.capacity.is_finite()
+            && self.refill.is_finite()
+            && self.capacity >= 1.0
+            && self.refill > 0.0
+            && self.capacity / self.refill <= MAX_REFILL_TIME.as_secs_f64()
+    }
+
+    // 回满所需的时间, 不超过 `MAX_REFILL_TIME`
+    fn ttl(&self) -> Duration {
+        Duration::try_from_secs_f64(self.capacity / self.refill)
+            .map_or(MAX_REFILL_TIME, |d| d.min(MAX_REFILL_TIME))
+    }
+}
+
+pub struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bucket: &Bucket) -> TokenBucket {
+        TokenBucket {
+            tokens: bucket.capacity,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn take(&mut self, bucket: &Bucket) -> bool {
+        self.take_at(bucket, Instant::now())
+    }
+
+    fn take_at(&mut self, bucket: &Bucket, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * bucket.refill).min(bucket.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // 空闲的时间足够回满, 与新建的桶没有区别
+    fn is_idle(&self, bucket: &Bucket, now: Instant) -> bool {
+        now.duration_since(self.last) >= bucket.ttl()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Limit {
+    Room,
+    P2P,
+    Broadcast,
+    Join,
+}
+
+impl Limit {
+    pub const ALL: [Limit; 4] = [Limit::Room, Limit::P2P, Limit::Broadcast, Limit::Join];
+
+    pub fn of(style: &ChatMessageType) -> Option<Limit> {
+        match style {
+            ChatMessageType::OneToOne(_) => Some(Limit::P2P),
+            ChatMessageType::RoomMessage(_) => Some(Limit::Room),
+            ChatMessageType::Broadcast => Some(Limit::Broadcast),
+            ChatMessageType::Join(_) => Some(Limit::Join),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Limit::Room => "room",
+            Limit::P2P => "p2p",
+            Limit::Broadcast => "broadcast",
+            Limit::Join => "join",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    // 单个连接的所有帧
+    pub connection: Bucket,
+    pub room: Bucket,
+    pub p2p: Bucket,
+    pub broadcast: Bucket,
+    pub join: Bucket,
+    // `violation_window` 内被拒绝的帧达到这个数就断开连接
+    pub max_violations: u32,
+    #[serde(with = "crate::config::seconds")]
+    pub violation_window: Duration,
+    // 用户的令牌桶放在 redis 中, 集群的各节点共用
+    pub use_redis: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            connection: Bucket {
+                capacity: 20.0,
+                refill: 10.0,
+            },
+            room: Bucket {
+                capacity: 10.0,
+                refill: 2.0,
+            },
+            p2p: Bucket {
+                capacity: 10.0,
+                refill: 2.0,
+            },
+            broadcast: Bucket {
+                capacity: 1.0,
+                refill: 1.0 / 60.0,
+            },
+            join: Bucket {
+                capacity: 5.0,
+                refill: 0.5,
+            },
+            max_violations: 10,
+            violation_window: Duration::from_secs(60),
+            use_redis: false,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn bucket(&self, limit: Limit) -> &Bucket {
+        match limit {
+            Limit::Room => &self.room,
+            Limit::P2P => &self.p2p,
+            Limit::Broadcast => &self.broadcast,
+            Limit::Join => &self.join,
+        }
+    }
+
+    pub fn bucket_mut(&mut self, limit: Limit) -> &mut Bucket {
+        match limit {
+            Limit::Room => &mut self.room,
+            Limit::P2P => &mut self.p2p,
+            Limit::Broadcast => &mut self.broadcast,
+            Limit::Join => &mut self.join,
+        }
+    }
+}
+
+// 每隔多久清理一次本地的空闲令牌桶
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// 令牌桶的存储, 单机放内存, 集群放 redis
+enum BucketStore {
+    Local(Mutex<LocalBuckets>),
+    Redis(Arc<RedisBuckets>),
+}
+
+struct LocalBuckets {
+    buckets: HashMap<(i32, Limit), TokenBucket>,
+    pruned: Instant,
+}
+
+impl LocalBuckets {
+    fn new() -> LocalBuckets {
+        LocalBuckets {
+            buckets: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, config: &RateLimitConfig, user_id: i32, limit: Limit, now: Instant) -> bool {
+        if now.duration_since(self.pruned) >= PRUNE_INTERVAL {
+            self.prune(config, now);
+        }
+        let bucket = config.bucket(limit);
+        self.buckets
+            .entry((user_id, limit))
+            .or_insert_with(|| TokenBucket::new(bucket))
+            .take_at(bucket, now)
+    }
+
+    // 删除已回满的桶, 否则见过的每个用户都会一直占用内存
+    fn prune(&mut self, config: &RateLimitConfig, now: Instant) {
+        self.buckets
+            .retain(|(_, limit), b| !b.is_idle(config.bucket(*limit), now));
+        self.pruned = now;
+    }
+}
+
+// KEYS[1] 为令牌桶, ARGV: 容量, 每秒回复数, 当前时间(毫秒), 过期时间(毫秒)
+const TOKEN_BUCKET_SCRIPT: &str = r"
+local b = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local capacity = tonumber(ARGV[1])
+local refill = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local tokens = tonumber(b[1]) or capacity
+local ts = tonumber(b[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) / 1000 * refill)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], ARGV[4])
+return allowed
+";
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: BucketStore,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, redis: RedisPool) -> RateLimiter {
+        let store = if config.use_redis {
+            BucketStore::Redis(Arc::new(RedisBuckets {
+                pool: redis,
+                script: Script::new(TOKEN_BUCKET_SCRIPT),
+            }))
+        } else {
+            BucketStore::Local(Mutex::new(LocalBuckets::new()))
+        };
+        RateLimiter { config, store }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    // 从用户的令牌桶中取一个令牌, 用户的所有连接共用. redis 不可用时放行,
+    // redis 调用在阻塞线程池上执行
+    pub async fn check(&self, user_id: i32, limit: Limit) -> bool {
+        let bucket = *self.config.bucket(limit);
+        match &self.store {
+            BucketStore::Local(buckets) => {
+                buckets
+                    .lock()
+                    .unwrap()
+                    .take(&self.config, user_id, limit, Instant::now())
+            }
+            BucketStore::Redis(redis) => {
+                let redis = redis.clone();
+                web::block(move || redis.take(user_id, limit, &bucket))
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!(error = ?e, "rate limit: redis failed");
+                        true
+                    })
+            }
+        }
+    }
+}
+
+struct RedisBuckets {
+    pool: RedisPool,
+    script: Script,
+}
+
+impl RedisBuckets {
+    // 阻塞调用
+    fn take(&self, user_id: i32, limit: Limit, bucket: &Bucket) -> redis::RedisResult<bool> {
+        let _timer = METRICS
+            .redis_duration
+            .with_label_values(&["rate_limit"])
+            .start_timer();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut conn = self.pool.get().map_err(|e| {
+            redis::RedisError::from((
+                redis::ErrorKind::IoError,
+                "get redis connection failed",
+                e.to_string(),
+            ))
+        })?;
+        let allowed: i32 = self
+            .script
+            .key(format!("ratelimit:{}:{}", user_id, limit.name()))
+            .arg(bucket.capacity)
+            .arg(bucket.refill)
+            .arg(now)
+            .arg(bucket.ttl().as_millis() as u64 + 1000)
+            .invoke(&mut *conn)?;
+        Ok(allowed == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUCKET: Bucket = Bucket {
+        capacity: 2.0,
+        refill: 1.0,
+    };
+
+    #[test]
+    fn parse_bucket() {
+        let b = Bucket::parse("10:0.5").unwrap();
+        assert_eq!(b.capacity, 10.0);
+        assert_eq!(b.refill, 0.5);
+        let b = Bucket::parse(" 1 : 0.0167 ").unwrap();
+        assert_eq!(b.capacity, 1.0);
+        assert_eq!(b.refill, 0.0167);
+        assert!(Bucket::parse("10").is_none());
+        assert!(Bucket::parse("10:").is_none());
+        assert!(Bucket::parse("a:1").is_none());
+        assert!(Bucket::parse("").is_none());
+    }
+
+    #[test]
+    fn bucket_validity() {
+        assert!(BUCKET.is_valid());
+        assert!(Bucket::parse("604800:1").unwrap().is_valid());
+        for value in &[
+            "0.5:1",
+            "5:0",
+            "5:-1",
+            "inf:1",
+            "1:inf",
+            "NaN:1",
+            "1:NaN",
+            "1e30:1e-10",
+            "604801:1",
+        ] {
+            assert!(!Bucket::parse(value).unwrap().is_valid(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn ttl_is_bounded() {
+        assert_eq!(BUCKET.ttl(), Duration::from_secs(2));
+        for value in &["inf:1", "1e30:1e-10", "1:0", "NaN:1"] {
+            assert_eq!(Bucket::parse(value).unwrap().ttl(), MAX_REFILL_TIME);
+        }
+    }
+
+    #[test]
+    fn take_until_empty() {
+        let mut b = TokenBucket::new(&BUCKET);
+        let now = b.last;
+        assert!(b.take_at(&BUCKET, now));
+        assert!(b.take_at(&BUCKET, now));
+        assert!(!b.take_at(&BUCKET, now));
+    }
+
+    #[test]
+    fn refill_over_time() {
+        let mut b = TokenBucket::new(&BUCKET);
+        let start = b.last;
+        assert!(b.take_at(&BUCKET, start));
+        assert!(b.take_at(&BUCKET, start));
+        // 半秒只回半个令牌
+        assert!(!b.take_at(&BUCKET, start + Duration::from_millis(500)));
+        assert!(b.take_at(&BUCKET, start + Duration::from_millis(1000)));
+        assert!(!b.take_at(&BUCKET, start + Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn refill_is_capped() {
+        let mut b = TokenBucket::new(&BUCKET);
+        let later = b.last + Duration::from_secs(3600);
+        assert!(b.take_at(&BUCKET, later));
+        assert!(b.take_at(&BUCKET, later));
+        assert!(!b.take_at(&BUCKET, later));
+    }
+
+    #[test]
+    fn prune_idle_buckets() {
+        let config = RateLimitConfig::default();
+        let mut local = LocalBuckets::new();
+        let start = local.pruned;
+        assert!(local.take(&config, 1, Limit::Room, start));
+        assert!(local.take(&config, 2, Limit::Room, start));
+        // room 桶 10 个令牌, 每秒 2 个, 5 秒回满
+        let later = start + Duration::from_secs(4);
+        assert!(local.take(&config, 2, Limit::Room, later));
+        local.prune(&config, start + Duration::from_secs(5));
+        assert!(!local.buckets.contains_key(&(1, Limit::Room)));
+        assert!(local.buckets.contains_key(&(2, Limit::Room)));
+    }
+
+    #[test]
+    fn prune_runs_periodically() {
+        let config = RateLimitConfig::default();
+        let mut local = LocalBuckets::new();
+        let start = local.pruned;
+        assert!(local.take(&config, 1, Limit::Join, start));
+        assert!(local.take(&config, 2, Limit::Join, start + PRUNE_INTERVAL));
+        assert_eq!(local.buckets.len(), 1);
+        assert_eq!(local.pruned, start + PRUNE_INTERVAL);
+    }
+}
@@ -1,3 +1,5 @@
+pub mod filter;
+pub mod limit;
 pub mod model;
 pub mod route;
 pub mod server;
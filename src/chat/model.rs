@@ -2,14 +2,23 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ChatMessageType {
-    // chat message
-    OneToOne(usize),
+    // 聊天消息, 单聊带对方的用户 id
+    OneToOne(i32),
     RoomMessage(String),
     Broadcast,
     // action
     Join(String),
     // message  ack
     Ack,
+    // 消息被拒绝, content 为原因
+    Error,
+    // 系统通知, 发给房间, 为 None 时发给单个用户
+    Notice(Option<String>),
+    // 联系人事件, 携带对方的用户 id
+    ContactRequest(i32),
+    ContactAccepted(i32),
+    // 服务即将关闭, 若干秒后重连
+    GoingAway(u64),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -33,4 +42,13 @@ impl ChatMessage {
             message_id: Some(message_id),
         }
     }
+
+    pub fn error(message_id: Option<String>, reason: &str) -> Self {
+        ChatMessage {
+            from: None,
+            style: ChatMessageType::Error,
+            content: Some(reason.to_owned()),
+            message_id,
+        }
+    }
 }
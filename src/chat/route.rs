@@ -1,30 +1,101 @@
+use super::filter;
+use super::limit::{Limit, RateLimiter, TokenBucket};
 use super::model::{ChatMessage, ChatMessageType};
 use super::server;
+use crate::api::auth::Identity;
+use crate::config::{Config, WebsocketConfig};
+use crate::cors;
+use crate::db::user::Role;
+use crate::metrics::METRICS;
 use actix::*;
-use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_http::ws::Codec;
+use actix_web::{http, web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::{info, info_span, warn, Span};
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+// 进程退出前置位, 之后不再接受 `/ws` 升级
+#[derive(Default)]
+pub struct Draining(AtomicBool);
+
+impl Draining {
+    // 只有第一次调用返回 true
+    pub fn start(&self) -> bool {
+        !self.0.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
 
 pub async fn chat_route(
     req: HttpRequest,
     stream: web::Payload,
+    identity: Identity,
     srv: web::Data<Addr<server::ChatServer>>,
+    limiter: web::Data<RateLimiter>,
+    config: web::Data<Config>,
+    draining: web::Data<Draining>,
 ) -> Result<HttpResponse, Error> {
+    if !cors::allows_websocket(&config, &req) {
+        return Ok(HttpResponse::Forbidden().body("origin not allowed"));
+    }
+    if draining.is_set() {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .header(
+                http::header::RETRY_AFTER,
+                config.websocket.reconnect_after.as_secs().to_string(),
+            )
+            .finish());
+    }
+    let remote_addr = req.connection_info().remote().map(|a| a.to_owned());
+    // 连接的 span, 每一帧的 span 都在它下面
+    let span = info_span!(
+        "ws_session",
+        user_id = identity.user_id,
+        remote_addr = remote_addr.as_deref().unwrap_or("-"),
+        session_id = Empty,
+    );
     let session = WsChatSession {
         id: 0,
+        span,
+        remote_addr,
+        ws: config.websocket,
+        max_length: config.filter.max_length,
+        user_id: identity.user_id,
+        role: identity.role,
         hb: Instant::now(),
         addr: srv.get_ref().clone(),
+        frames: TokenBucket::new(&limiter.config().connection),
+        violations: 0,
+        violation_since: Instant::now(),
+        limiter,
     };
-    ws::start(session, &req, stream)
+    // 超过 max_frame_size 的帧在解码时被拒绝, 不会读入完整的 payload
+    let codec = Codec::new().max_size(config.websocket.max_frame_size);
+    let mut res = ws::handshake(&req)?;
+    Ok(res.streaming(ws::WebsocketContext::with_codec(session, stream, codec)))
 }
 
 struct WsChatSession {
     id: usize,
+    span: Span,
+    remote_addr: Option<String>,
+    ws: WebsocketConfig,
+    // `filter.max_length`, 没有按类型设置上限时使用
+    max_length: usize,
+    user_id: i32,
+    role: Role,
     hb: Instant,
     addr: Addr<server::ChatServer>,
+    limiter: web::Data<RateLimiter>,
+    // 连接的令牌桶, 每收到一帧取一个
+    frames: TokenBucket,
+    violations: u32,
+    violation_since: Instant,
 }
 
 impl Actor for WsChatSession {
@@ -32,16 +103,21 @@ impl Actor for WsChatSession {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         self.hb(ctx);
-        // TODO wait for auth, if unauth close connecting
         let addr = ctx.address();
         self.addr
             .send(server::Connect {
-                addr: addr.recipient(),
+                addr: addr.clone().recipient(),
+                kick: addr.recipient(),
+                user_id: self.user_id,
+                remote_addr: self.remote_addr.clone(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
-                    Ok(res) => act.id = res,
+                    Ok(res) => {
+                        act.id = res;
+                        act.span.record("session_id", res);
+                    }
                     _ => ctx.stop(),
                 }
                 fut::ready(())
@@ -62,9 +138,36 @@ impl Handler<server::Message> for WsChatSession {
     }
 }
 
+impl Handler<server::Kick> for WsChatSession {
+    type Result = ();
+    fn handle(&mut self, msg: server::Kick, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: msg.code,
+            description: Some(msg.reason),
+        }));
+        ctx.stop();
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         let msg = match msg {
+            Err(ws::ProtocolError::Overflow) => {
+                METRICS.oversized("frame");
+                let _span = self.span.enter();
+                warn!(
+                    limit = self.ws.max_frame_size,
+                    "frame too large, disconnecting"
+                );
+                let err = ChatMessage::error(None, "frame too large");
+                ctx.text(serde_json::to_string(&err).unwrap());
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Size,
+                    description: Some("frame too large".to_owned()),
+                }));
+                ctx.stop();
+                return;
+            }
             Err(_) => {
                 ctx.stop();
                 return;
@@ -81,64 +184,19 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                 self.hb = Instant::now();
             }
             ws::Message::Text(text) => {
+                let received = Instant::now();
+                let _frame = info_span!(parent: &self.span, "frame").entered();
                 let msg: std::result::Result<ChatMessage, serde_json::Error> =
                     serde_json::from_str(text.as_str());
                 match msg {
-                    Ok(msg) => match msg.style {
-                        ChatMessageType::OneToOne(id) => {
-                            self.addr.do_send(server::P2PMessage {
-                                id: self.id,
-                                msg: msg.content.unwrap_or_default(),
-                                other_id: id,
-                            });
-                            if let Some(message_id) = msg.message_id {
-                                let ack =
-                                    serde_json::to_string(&ChatMessage::ack(message_id)).unwrap();
-                                ctx.text(ack);
-                            }
-                        }
-                        ChatMessageType::RoomMessage(room) => {
-                            self.addr.do_send(server::RoomMessage {
-                                id: self.id,
-                                msg: msg.content.unwrap_or_default(),
-                                room: room,
-                            });
-                            if let Some(message_id) = msg.message_id {
-                                let ack =
-                                    serde_json::to_string(&ChatMessage::ack(message_id)).unwrap();
-                                ctx.text(ack);
-                            }
-                        }
-                        ChatMessageType::Broadcast => {
-                            self.addr.do_send(server::BoardcastMessage {
-                                id: self.id,
-                                msg: msg.content.unwrap_or_default(),
-                            });
-                            if let Some(message_id) = msg.message_id {
-                                let ack =
-                                    serde_json::to_string(&ChatMessage::ack(message_id)).unwrap();
-                                ctx.text(ack);
-                            }
-                        }
-                        ChatMessageType::Join(room) => {
-                            self.addr.do_send(server::Join {
-                                id: self.id,
-                                name: room,
-                            });
-                            if let Some(message_id) = msg.message_id {
-                                let ack =
-                                    serde_json::to_string(&ChatMessage::ack(message_id)).unwrap();
-                                ctx.text(ack);
-                            }
-                        }
-                        _ => (),
-                    },
+                    Ok(msg) => self.admit(msg, received, ctx),
                     Err(e) => {
-                        println!("{:?}", e);
+                        METRICS.dropped("invalid");
+                        warn!(error = %e, "invalid message");
                     }
                 }
             }
-            ws::Message::Binary(_) => println!("Unexpected binary message"),
+            ws::Message::Binary(_) => warn!("unexpected binary message"),
             ws::Message::Close(_) => {
                 ctx.stop();
             }
@@ -151,10 +209,165 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
 }
 
 impl WsChatSession {
+    // content 超过该类型的上限时回复错误帧, 不投递, 不断开连接
+    fn fits(&self, msg: &ChatMessage, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        let (name, limit) = match self.ws.max_content_length.of(&msg.style, self.max_length) {
+            Some(l) => l,
+            None => return true,
+        };
+        let content = msg.content.as_deref().unwrap_or("");
+        let reason = match filter::too_long(content, limit) {
+            Some(reason) => reason,
+            None => return true,
+        };
+
+        METRICS.oversized(name);
+        warn!(r#type = name, limit, "message too long");
+        let err = ChatMessage::error(msg.message_id.clone(), &reason);
+        ctx.text(serde_json::to_string(&err).unwrap());
+        false
+    }
+
+    // 先按连接再按用户限流, 通过后转发. 用户的令牌桶可能在 redis 中, 检查期间
+    // 暂停处理后续的帧, 保证消息按顺序转发
+    fn admit(&mut self, msg: ChatMessage, received: Instant, ctx: &mut ws::WebsocketContext<Self>) {
+        if !self.frames.take(&self.limiter.config().connection) {
+            self.rate_limited(msg.message_id, ctx);
+            return;
+        }
+        let limit = match Limit::of(&msg.style) {
+            Some(limit) => limit,
+            None => return self.forward(msg, received, ctx),
+        };
+        let (limiter, user_id, span) = (self.limiter.clone(), self.user_id, Span::current());
+        async move { limiter.check(user_id, limit).await }
+            .into_actor(self)
+            .then(move |allowed, act, ctx| {
+                let _span = span.enter();
+                if allowed {
+                    act.forward(msg, received, ctx);
+                } else {
+                    act.rate_limited(msg.message_id, ctx);
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn forward(
+        &mut self,
+        msg: ChatMessage,
+        received: Instant,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        // 限流之后再检查长度, 过长的消息同样消耗令牌
+        if !self.fits(&msg, ctx) {
+            return;
+        }
+        match msg.style {
+            ChatMessageType::OneToOne(to) => {
+                let outgoing = server::P2PMessage {
+                    id: self.id,
+                    msg: msg.content.unwrap_or_default(),
+                    to,
+                    span: Span::current(),
+                };
+                self.route(outgoing, ctx);
+                self.ack(msg.message_id, received, ctx);
+            }
+            ChatMessageType::RoomMessage(room) => {
+                let outgoing = server::RoomMessage {
+                    id: self.id,
+                    msg: msg.content.unwrap_or_default(),
+                    room: room,
+                    span: Span::current(),
+                };
+                self.route(outgoing, ctx);
+                self.ack(msg.message_id, received, ctx);
+            }
+            ChatMessageType::Broadcast if self.role != Role::Admin => {
+                METRICS.dropped("permission_denied");
+                let err = ChatMessage::error(msg.message_id, "permission denied");
+                ctx.text(serde_json::to_string(&err).unwrap());
+            }
+            ChatMessageType::Broadcast => {
+                let outgoing = server::BoardcastMessage {
+                    id: self.id,
+                    msg: msg.content.unwrap_or_default(),
+                    span: Span::current(),
+                };
+                self.route(outgoing, ctx);
+                self.ack(msg.message_id, received, ctx);
+            }
+            ChatMessageType::Join(room) => {
+                let outgoing = server::Join {
+                    id: self.id,
+                    name: room,
+                    span: Span::current(),
+                };
+                self.route(outgoing, ctx);
+                self.ack(msg.message_id, received, ctx);
+            }
+            _ => (),
+        }
+    }
+
+    // ChatServer 在线程池上落库, 等它投递完再处理后续的帧, 同一连接的消息按顺序投递
+    fn route<M>(&self, msg: M, ctx: &mut ws::WebsocketContext<Self>)
+    where
+        M: Message<Result = Result<(), ()>> + Send + 'static,
+        server::ChatServer: Handler<M>,
+    {
+        self.addr
+            .send(msg)
+            .into_actor(self)
+            .then(|_, _, _| fut::ready(()))
+            .wait(ctx);
+    }
+
+    // 被限流时回复错误帧, 短时间内多次被限流则断开连接
+    fn rate_limited(&mut self, message_id: Option<String>, ctx: &mut ws::WebsocketContext<Self>) {
+        METRICS.dropped("rate_limited");
+        let err = ChatMessage::error(message_id, "rate limited");
+        ctx.text(serde_json::to_string(&err).unwrap());
+
+        let config = self.limiter.config();
+        let now = Instant::now();
+        if now.duration_since(self.violation_since) > config.violation_window {
+            self.violation_since = now;
+            self.violations = 0;
+        }
+        self.violations += 1;
+        if self.violations >= config.max_violations {
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Policy,
+                description: Some("too many messages".to_owned()),
+            }));
+            ctx.stop();
+        }
+    }
+
+    fn ack(
+        &self,
+        message_id: Option<String>,
+        received: Instant,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if let Some(message_id) = message_id {
+            let ack = serde_json::to_string(&ChatMessage::ack(message_id)).unwrap();
+            ctx.text(ack);
+            METRICS
+                .ack_latency
+                .observe(received.elapsed().as_secs_f64());
+        }
+    }
+
     fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
-                println!("Websocket Client heartbeat failed, disconnecting");
+        ctx.run_interval(self.ws.heartbeat_interval, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.ws.client_timeout {
+                METRICS.heartbeat_timeouts.inc();
+                let _span = act.span.enter();
+                info!("heartbeat failed, disconnecting");
                 ctx.stop();
                 return;
             }
@@ -1,7 +1,26 @@
+use super::filter::{FilterChain, Filtered, Flag, Outgoing, Target};
 use super::model::{ChatMessage, ChatMessageType};
+use crate::db::{
+    self, error::Error, message::QueryMessage, moderation::SanctionKind, search::SearchEngine,
+    storage::Storage, Repository,
+};
+use crate::metrics::METRICS;
+use actix::fut::wrap_future;
 use actix::prelude::*;
+use actix_web_actors::ws::CloseCode;
+use chrono::NaiveDateTime;
 use rand::{self, rngs::ThreadRng, Rng};
-use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info, info_span, Span};
+use tracing_futures::Instrument;
+
+// 上线时最多补发的广播条数
+const MAX_PENDING_BROADCASTS: i64 = 50;
+// 吞吐量统计的窗口, 秒
+const THROUGHPUT_WINDOW: u64 = 60;
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -9,10 +28,21 @@ pub struct Message {
     pub text: String,
 }
 
+// 让会话断开, 如用户被踢出或停用
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kick {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
 #[derive(Message)]
 #[rtype(usize)]
 pub struct Connect {
     pub addr: Recipient<Message>,
+    pub kick: Recipient<Kick>,
+    pub user_id: i32,
+    pub remote_addr: Option<String>,
 }
 
 #[derive(Message)]
@@ -21,68 +51,426 @@ pub struct Disconnect {
     pub id: usize,
 }
 
+// `span` 为收到这一帧时的 span, 路由和落库都记在它下面. 落库和投递后才回复,
+// 会话等到回复再处理下一帧, 同一连接的消息按顺序投递
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), ()>")]
 pub struct RoomMessage {
     pub id: usize,
     pub msg: String,
     pub room: String,
+    pub span: Span,
 }
 
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), ()>")]
 pub struct P2PMessage {
     pub id: usize,
     pub msg: String,
-    pub other_id: usize,
+    // 接收者的用户 id, 对方离线时消息只落库
+    pub to: i32,
+    pub span: Span,
 }
 
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), ()>")]
 pub struct BoardcastMessage {
     pub id: usize,
     pub msg: String,
+    pub span: Span,
+}
+
+// 管理接口发布的全站公告
+#[derive(Message)]
+#[rtype(result = "Result<QueryMessage, Error>")]
+pub struct Announce {
+    pub user_id: i32,
+    pub msg: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sanction {
+    Mute,
+    Unmute,
+    Kick,
+    Ban,
+    Unban,
+}
+
+// 执行已落库的处罚决定, 全站范围时 `room` 为 None
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Moderate {
+    pub room: Option<String>,
+    pub user_id: i32,
+    pub sanction: Sanction,
+    pub notice: String,
+}
+
+// 推送给用户的每个会话, 如联系人请求
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Notify {
+    pub user_id: i32,
+    pub msg: ChatMessage,
+}
+
+// `users` 中至少有一个在线会话的用户
+pub struct OnlineUsers {
+    pub users: Vec<i32>,
 }
 
+impl actix::Message for OnlineUsers {
+    type Result = HashSet<i32>;
+}
+
+// 服务器已知的房间及其实时统计
 pub struct ListRooms;
 
+// 进程退出前关闭所有会话并提示重连, 返回通知关闭的会话数
+#[derive(Message)]
+#[rtype(usize)]
+pub struct Shutdown {
+    pub reconnect_after: u64,
+}
+
 impl actix::Message for ListRooms {
-    type Result = Vec<String>;
+    type Result = Vec<RoomInfo>;
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomInfo {
+    pub name: String,
+    // 房间内的会话数和其中的不同用户数
+    pub sessions: usize,
+    pub users: usize,
+    // 启动以来投递的房间消息数
+    pub messages: u64,
+}
+
+// 所有已连接的会话, 用于管理面板
+pub struct ListSessions;
+
+impl actix::Message for ListSessions {
+    type Result = Vec<SessionInfo>;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    // 随机的 64 位 id, 超出 JavaScript 数字的精度, 以字符串返回
+    pub session_id: String,
+    pub user_id: i32,
+    pub connected_at: NaiveDateTime,
+    pub remote_addr: Option<String>,
+    pub rooms: Vec<String>,
+}
+
+// 关闭一个会话, 未连接时返回 false
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct CloseSession {
+    pub id: usize,
+    pub reason: String,
+}
+
+// 启动以来和最近一分钟投递的消息数
+pub struct GetThroughput;
+
+impl actix::Message for GetThroughput {
+    type Result = ThroughputStats;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThroughputStats {
+    pub uptime_secs: u64,
+    pub total: u64,
+    // 按 `ChatMessageType` 分类, 与 `chat_messages_routed_total` 相同
+    pub by_type: BTreeMap<&'static str, u64>,
+    pub last_minute: u64,
+    pub per_second: f64,
+}
+
+// 存活探针, 存储调用不在 actor 上执行, 数据库慢时也能及时回复
 #[derive(Message)]
 #[rtype(result = "()")]
+pub struct Ping;
+
+// 还在线程池上执行的存储调用数, 关闭时等它们完成
+#[derive(Message)]
+#[rtype(usize)]
+pub struct Pending;
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ()>")]
 pub struct Join {
     pub id: usize,
     pub name: String,
+    pub span: Span,
+}
+
+struct Session {
+    addr: Recipient<Message>,
+    kick: Recipient<Kick>,
+    user_id: i32,
+    remote_addr: Option<String>,
+    connected_at: NaiveDateTime,
+}
+
+// 投递的消息数, 最近一分钟按秒计数
+struct Throughput {
+    started: Instant,
+    totals: BTreeMap<&'static str, u64>,
+    // (启动后的秒数, 条数)
+    recent: VecDeque<(u64, u64)>,
+}
+
+impl Throughput {
+    fn new() -> Throughput {
+        Throughput {
+            started: Instant::now(),
+            totals: BTreeMap::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, message_type: &'static str) {
+        *self.totals.entry(message_type).or_insert(0) += 1;
+        let now = self.started.elapsed().as_secs();
+        match self.recent.back_mut() {
+            Some((sec, n)) if *sec == now => *n += 1,
+            _ => self.recent.push_back((now, 1)),
+        }
+        self.trim(now);
+    }
+
+    fn trim(&mut self, now: u64) {
+        while let Some((sec, _)) = self.recent.front() {
+            if sec + THROUGHPUT_WINDOW > now {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    fn stats(&mut self) -> ThroughputStats {
+        let uptime = self.started.elapsed();
+        self.trim(uptime.as_secs());
+        let last_minute = self.recent.iter().map(|(_, n)| n).sum();
+        // 启动不满一分钟时按已运行的时间计算
+        let window = uptime.as_secs_f64().min(THROUGHPUT_WINDOW as f64).max(1.0);
+        ThroughputStats {
+            uptime_secs: uptime.as_secs(),
+            total: self.totals.values().sum(),
+            by_type: self.totals.clone(),
+            last_minute,
+            per_second: last_minute as f64 / window,
+        }
+    }
+}
+
+// 投递前检查的结果, 在线程池上得到, 回到 actor 上回复发送者
+enum Refused {
+    Sanctioned(SanctionKind),
+    NotInRoom,
+    UnknownUser,
+    Blocked,
+    ContactsOnly,
+    // 检查的查询失败, 不投递
+    Unavailable(&'static str, Error),
+}
+
+fn check<T>(check: &'static str, r: Result<T, Error>) -> Result<T, Refused> {
+    r.map_err(|e| Refused::Unavailable(check, e))
+}
+
+// 线程池本身出错时同样不投递
+fn checked<T>(r: Result<Result<T, Refused>, Error>) -> Result<T, Refused> {
+    r.unwrap_or_else(|e| Err(Refused::Unavailable("store", e)))
+}
+
+fn check_sanction(
+    s: &dyn Storage,
+    room: Option<&str>,
+    user_id: i32,
+    kind: SanctionKind,
+) -> Result<(), Refused> {
+    match check("sanction", s.is_sanctioned(room, user_id, kind))? {
+        true => Err(Refused::Sanctioned(kind)),
+        false => Ok(()),
+    }
+}
+
+// 消息落库并写入搜索索引和过滤标记, 失败时不影响消息投递
+fn persist(
+    s: &dyn Storage,
+    search: &dyn SearchEngine,
+    r: Result<QueryMessage, Error>,
+    flags: &[Flag],
+) -> Result<QueryMessage, Error> {
+    let r = r.and_then(|m| search.index(&m).map(|_| m));
+    match r {
+        Ok(ref m) => {
+            for flag in flags {
+                if let Err(e) = s.flag_message(m.message_id, &flag.filter, &flag.reason) {
+                    error!(error = ?e, "record message flag failed");
+                }
+            }
+        }
+        Err(ref e) => error!(error = ?e, "persist message failed"),
+    }
+    r
 }
 
 pub struct ChatServer {
-    sessions: HashMap<usize, Recipient<Message>>,
+    sessions: HashMap<usize, Session>,
     rooms: HashMap<String, HashSet<usize>>,
     rng: ThreadRng,
+    repo: Repository,
+    search: Arc<dyn SearchEngine>,
+    filters: FilterChain,
+    // 收到 Shutdown 后为 Some(重连间隔), 之后连上的会话立即关闭
+    going_away: Option<u64>,
+    throughput: Throughput,
+    room_messages: HashMap<String, u64>,
+    in_flight: usize,
 }
 
-impl Default for ChatServer {
-    fn default() -> ChatServer {
+impl ChatServer {
+    pub fn new(
+        repo: Repository,
+        search: Arc<dyn SearchEngine>,
+        filters: FilterChain,
+    ) -> ChatServer {
         let rooms = HashMap::new();
         ChatServer {
             sessions: HashMap::new(),
             rooms,
             rng: rand::thread_rng(),
+            repo,
+            search,
+            filters,
+            going_away: None,
+            throughput: Throughput::new(),
+            room_messages: HashMap::new(),
+            in_flight: 0,
+        }
+    }
+
+    // 存储调用在阻塞线程池上执行, 聊天服务的线程只做内存中的路由, 不被数据库拖慢
+    fn store<F, T>(
+        &mut self,
+        span: &Span,
+        f: F,
+    ) -> impl ActorFuture<Output = Result<T, Error>, Actor = Self>
+    where
+        F: FnOnce(&dyn Storage, &dyn SearchEngine) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.in_flight += 1;
+        let (repo, search) = (self.repo.clone(), self.search.clone());
+        let fut = async move { repo.store(move |s| Ok(f(s, search.as_ref()))).await }
+            .instrument(span.clone());
+        wrap_future::<_, Self>(fut).map(|r, act, _| {
+            act.in_flight -= 1;
+            r
+        })
+    }
+
+    // 内容过滤, 被拒绝时给发送者回复错误帧
+    fn filter(&self, id: usize, user_id: i32, target: Target, content: &str) -> Option<Filtered> {
+        let msg = Outgoing {
+            user_id,
+            target,
+            content,
+        };
+        match self.filters.run(&msg) {
+            Ok(filtered) => Some(filtered),
+            Err(reason) => {
+                METRICS.dropped("filtered");
+                self.send_error(id, reason.as_str());
+                None
+            }
+        }
+    }
+
+    // 广播推送给所有在线会话, 已落库时在线用户的广播记为已送达
+    fn broadcast(
+        &mut self,
+        content: String,
+        skip_id: usize,
+        persisted: Option<i64>,
+        ctx: &mut Context<Self>,
+    ) {
+        let send_msg = ChatMessage {
+            from: if skip_id == 0 { None } else { Some(skip_id) },
+            style: ChatMessageType::Broadcast,
+            content: Some(content),
+            message_id: None,
+        };
+        let send_str = serde_json::to_string(&send_msg).unwrap();
+        self.send_boardcast(send_str.as_str(), skip_id);
+        self.routed("broadcast");
+        if let Some(message_id) = persisted {
+            let online: Vec<i32> = self.sessions.values().map(|s| s.user_id).collect();
+            let marked = self.store(&Span::current(), move |s, _| {
+                s.mark_broadcast_read(&online, message_id)
+            });
+            ctx.spawn(marked.map(|r, _, _| {
+                if let Err(e) = r.and_then(|r| r) {
+                    error!(error = ?e, "mark broadcast read failed");
+                }
+            }));
+        }
+    }
+
+    // 补发用户离线期间的广播
+    fn send_pending_broadcasts(&mut self, id: usize, user_id: i32, ctx: &mut Context<Self>) {
+        let pending = self.store(&Span::current(), move |s, _| {
+            let pending = s.pending_broadcasts(user_id, MAX_PENDING_BROADCASTS)?;
+            if let Some(last) = pending.last() {
+                s.mark_broadcast_read(&[user_id], last.message_id)?;
+            }
+            Ok(pending)
+        });
+        ctx.spawn(pending.map(move |r, act, _| {
+            let pending = match r.and_then(|r| r) {
+                Ok(pending) => pending,
+                Err(e) => {
+                    error!(error = ?e, "query pending broadcasts failed");
+                    return;
+                }
+            };
+            for m in pending {
+                let send_msg = ChatMessage {
+                    from: None,
+                    style: ChatMessageType::Broadcast,
+                    content: Some(m.content),
+                    message_id: None,
+                };
+                act.send_p2p_message(&id, serde_json::to_string(&send_msg).unwrap().as_str());
+            }
+        }));
+    }
+
+    // 会话的邮箱已满或已关闭时丢弃
+    fn deliver(session: &Session, message: &str) {
+        let r = session.addr.do_send(Message {
+            text: message.to_owned(),
+        });
+        if r.is_err() {
+            METRICS.dropped("undeliverable");
         }
     }
-}
 
-impl ChatServer {
     fn send_message(&self, room: &str, message: &str, skip_id: usize) {
         if let Some(sessions) = self.rooms.get(room) {
             for id in sessions {
                 if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        let _ = addr.do_send(Message {
-                            text: message.to_owned(),
-                        });
+                    if let Some(session) = self.sessions.get(id) {
+                        Self::deliver(session, message);
                     }
                 }
             }
@@ -90,22 +478,98 @@ impl ChatServer {
     }
 
     fn send_boardcast(&self, message: &str, skip_id: usize) {
-        for (id, addr) in &self.sessions {
+        for (id, session) in &self.sessions {
             if *id != skip_id {
-                let _ = addr.do_send(Message {
-                    text: message.to_owned(),
-                });
+                Self::deliver(session, message);
             }
         }
     }
 
     fn send_p2p_message(&self, id: &usize, message: &str) {
-        if let Some(addr) = self.sessions.get(id) {
-            let _ = addr.do_send(Message {
-                text: message.to_owned(),
+        if let Some(session) = self.sessions.get(id) {
+            Self::deliver(session, message);
+        }
+    }
+
+    fn routed(&mut self, message_type: &'static str) {
+        METRICS.routed(message_type);
+        self.throughput.record(message_type);
+    }
+
+    fn update_gauges(&self) {
+        METRICS.sessions.set(self.sessions.len() as i64);
+        let rooms = self.rooms.values().filter(|s| !s.is_empty()).count();
+        METRICS.rooms.set(rooms as i64);
+    }
+
+    fn user_of(&self, id: usize) -> Option<i32> {
+        self.sessions.get(&id).map(|s| s.user_id)
+    }
+
+    fn sessions_of(&self, user_id: i32) -> Vec<usize> {
+        self.sessions
+            .iter()
+            .filter(|(_, s)| s.user_id == user_id)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn send_error(&self, id: usize, reason: &str) {
+        let err = serde_json::to_string(&ChatMessage::error(None, reason)).unwrap();
+        self.send_p2p_message(&id, err.as_str());
+    }
+
+    // 投递前的检查查询失败时不投递, 提示发送者稍后重试
+    fn unavailable(&self, id: usize, check: &'static str, e: Error) {
+        error!(check, error = ?e, "check before delivery failed");
+        METRICS.dropped("unavailable");
+        self.send_error(id, "message not delivered, try again later");
+    }
+
+    // 先发送重连提示, 再以 1001 (going away) 关闭连接
+    fn send_going_away(&self, id: usize, reconnect_after: u64) {
+        if let Some(session) = self.sessions.get(&id) {
+            let hint = ChatMessage {
+                from: None,
+                style: ChatMessageType::GoingAway(reconnect_after),
+                content: Some("server shutting down".to_owned()),
+                message_id: None,
+            };
+            let _ = session.addr.do_send(Message {
+                text: serde_json::to_string(&hint).unwrap(),
+            });
+            let _ = session.kick.do_send(Kick {
+                code: CloseCode::Away,
+                reason: format!("server going away, reconnect in {}s", reconnect_after),
             });
         }
     }
+
+    fn notice(room: Option<&str>, notice: &str) -> String {
+        let send_msg = ChatMessage {
+            from: None,
+            style: ChatMessageType::Notice(room.map(|r| r.to_owned())),
+            content: Some(notice.to_owned()),
+            message_id: None,
+        };
+        serde_json::to_string(&send_msg).unwrap()
+    }
+
+    // 没有通过投递前的检查, 给发送者回复错误帧; 查询失败时同样不投递,
+    // 数据库故障不能解除处罚
+    fn refuse(&self, id: usize, refused: Refused) {
+        let (reason, message) = match refused {
+            Refused::Sanctioned(SanctionKind::Mute) => ("sanctioned", "muted"),
+            Refused::Sanctioned(SanctionKind::Ban) => ("sanctioned", "banned"),
+            Refused::NotInRoom => ("not_in_room", "not in the room"),
+            Refused::UnknownUser => ("unknown_user", "user not found"),
+            Refused::Blocked => ("blocked", "blocked by the user"),
+            Refused::ContactsOnly => ("contacts_only", "only contacts can message the user"),
+            Refused::Unavailable(check, e) => return self.unavailable(id, check, e),
+        };
+        METRICS.dropped(reason);
+        self.send_error(id, message);
+    }
 }
 
 impl Actor for ChatServer {
@@ -115,10 +579,24 @@ impl Actor for ChatServer {
 impl Handler<Connect> for ChatServer {
     type Result = usize;
 
-    fn handle(&mut self, msg: Connect, _: &mut Self::Context) -> Self::Result {
-        println!("Someone joined");
+    fn handle(&mut self, msg: Connect, ctx: &mut Self::Context) -> Self::Result {
         let id = self.rng.gen::<usize>();
-        self.sessions.insert(id, msg.addr);
+        info!(session_id = id, user_id = msg.user_id, "session connected");
+        self.sessions.insert(
+            id,
+            Session {
+                addr: msg.addr,
+                kick: msg.kick,
+                user_id: msg.user_id,
+                remote_addr: msg.remote_addr,
+                connected_at: db::moderation::now(),
+            },
+        );
+        self.send_pending_broadcasts(id, msg.user_id, ctx);
+        self.update_gauges();
+        if let Some(reconnect_after) = self.going_away {
+            self.send_going_away(id, reconnect_after);
+        }
         id
     }
 }
@@ -126,7 +604,7 @@ impl Handler<Connect> for ChatServer {
 impl Handler<Disconnect> for ChatServer {
     type Result = ();
     fn handle(&mut self, msg: Disconnect, _: &mut Self::Context) {
-        println!("Someone disconnet");
+        info!(session_id = msg.id, "session disconnected");
         let mut rooms: Vec<String> = Vec::new();
         if self.sessions.remove(&msg.id).is_some() {
             for (name, sessions) in &mut self.rooms {
@@ -136,6 +614,8 @@ impl Handler<Disconnect> for ChatServer {
             }
         }
 
+        self.update_gauges();
+
         // for room in rooms {
         //     self.send_message(&room, "Someone disconnect", 0);
         // }
@@ -143,47 +623,191 @@ impl Handler<Disconnect> for ChatServer {
 }
 
 impl Handler<RoomMessage> for ChatServer {
-    type Result = ();
-    fn handle(&mut self, msg: RoomMessage, _: &mut Self::Context) {
-        let room = msg.room.clone();
-        let skip_id = msg.id.clone();
-        let send_msg = ChatMessage {
-            from: Some(msg.id),
-            style: ChatMessageType::RoomMessage(msg.room),
-            content: Some(msg.msg),
-            message_id: None,
+    type Result = ResponseActFuture<Self, Result<(), ()>>;
+    fn handle(&mut self, msg: RoomMessage, _: &mut Self::Context) -> Self::Result {
+        let RoomMessage {
+            id,
+            msg,
+            room,
+            span,
+        } = msg;
+        let span = info_span!(parent: &span, "route", kind = "room", room = %room);
+        let _enter = span.enter();
+        let user_id = match self.user_of(id) {
+            Some(user_id) => user_id,
+            None => return Box::new(fut::ok(())),
         };
-        let send_str = serde_json::to_string(&send_msg).unwrap();
-        self.send_message(&room, send_str.as_str(), skip_id);
+        // 只有加入了房间的会话能发言
+        if !self.rooms.get(&room).is_some_and(|s| s.contains(&id)) {
+            self.refuse(id, Refused::NotInRoom);
+            return Box::new(fut::ok(()));
+        }
+        let filtered = match self.filter(id, user_id, Target::Room(&room), &msg) {
+            Some(filtered) => filtered,
+            None => return Box::new(fut::ok(())),
+        };
+        let name = room.clone();
+        let stored = self.store(&span, move |s, search| {
+            // 加入后才被封禁的用户仍在成员中
+            check_sanction(s, Some(&name), user_id, SanctionKind::Ban)?;
+            check_sanction(s, Some(&name), user_id, SanctionKind::Mute)?;
+            let r = s.add_room_message(user_id, &name, &filtered.content);
+            let _ = persist(s, search, r, &filtered.flags);
+            Ok(filtered.content)
+        });
+        let span = span.clone();
+        Box::new(stored.map(move |r, act, _| {
+            let _enter = span.enter();
+            let content = match checked(r) {
+                Ok(content) => content,
+                Err(refused) => {
+                    act.refuse(id, refused);
+                    return Ok(());
+                }
+            };
+            let send_msg = ChatMessage {
+                from: Some(id),
+                style: ChatMessageType::RoomMessage(room.clone()),
+                content: Some(content),
+                message_id: None,
+            };
+            let send_str = serde_json::to_string(&send_msg).unwrap();
+            act.send_message(&room, send_str.as_str(), id);
+            *act.room_messages.entry(room).or_insert(0) += 1;
+            act.routed("room");
+            Ok(())
+        }))
     }
 }
 
 impl Handler<P2PMessage> for ChatServer {
-    type Result = ();
-    fn handle(&mut self, msg: P2PMessage, _: &mut Self::Context) {
-        let other_id = msg.other_id.clone();
-        let send_msg = ChatMessage {
-            from: Some(msg.id),
-            style: ChatMessageType::OneToOne(msg.other_id),
-            content: Some(msg.msg),
-            message_id: None,
+    type Result = ResponseActFuture<Self, Result<(), ()>>;
+    fn handle(&mut self, msg: P2PMessage, _: &mut Self::Context) -> Self::Result {
+        let P2PMessage { id, msg, to, span } = msg;
+        let span = info_span!(parent: &span, "route", kind = "one_to_one", to);
+        let _enter = span.enter();
+        let from = match self.user_of(id) {
+            Some(from) => from,
+            None => return Box::new(fut::ok(())),
         };
-        let send_str = serde_json::to_string(&send_msg).unwrap();
-        self.send_p2p_message(&other_id, send_str.as_str());
+        let filtered = match self.filter(id, from, Target::User, &msg) {
+            Some(filtered) => filtered,
+            None => return Box::new(fut::ok(())),
+        };
+        let stored = self.store(&span, move |s, search| {
+            check_sanction(s, None, from, SanctionKind::Mute)?;
+            match s.find_user(to) {
+                Ok(_) => (),
+                Err(Error::NotFound) => return Err(Refused::UnknownUser),
+                Err(e) => return Err(Refused::Unavailable("user", e)),
+            }
+            if check("block", s.is_blocked(to, from))? {
+                return Err(Refused::Blocked);
+            }
+            if !check("contact", s.may_message(from, to))? {
+                return Err(Refused::ContactsOnly);
+            }
+            // 先落库再投递, 对方离线时之后也能搜索到
+            let r = s.add_p2p_message(from, to, &filtered.content);
+            let _ = persist(s, search, r, &filtered.flags);
+            Ok(filtered.content)
+        });
+        let span = span.clone();
+        Box::new(stored.map(move |r, act, _| {
+            let _enter = span.enter();
+            let content = match checked(r) {
+                Ok(content) => content,
+                Err(refused) => {
+                    act.refuse(id, refused);
+                    return Ok(());
+                }
+            };
+            let send_msg = ChatMessage {
+                from: Some(id),
+                style: ChatMessageType::OneToOne(from),
+                content: Some(content),
+                message_id: None,
+            };
+            let send_str = serde_json::to_string(&send_msg).unwrap();
+            for id in act.sessions_of(to) {
+                act.send_p2p_message(&id, send_str.as_str());
+            }
+            act.routed("one_to_one");
+            Ok(())
+        }))
     }
 }
 
 impl Handler<BoardcastMessage> for ChatServer {
-    type Result = ();
-    fn handle(&mut self, msg: BoardcastMessage, _: &mut Self::Context) {
-        let send_msg = ChatMessage {
-            from: Some(msg.id),
-            style: ChatMessageType::Broadcast,
-            content: Some(msg.msg),
-            message_id: None,
+    type Result = ResponseActFuture<Self, Result<(), ()>>;
+    fn handle(&mut self, msg: BoardcastMessage, _: &mut Self::Context) -> Self::Result {
+        let BoardcastMessage { id, msg, span } = msg;
+        let span = info_span!(parent: &span, "route", kind = "broadcast");
+        let _enter = span.enter();
+        let user_id = match self.user_of(id) {
+            Some(user_id) => user_id,
+            None => return Box::new(fut::ok(())),
         };
-        let send_str = serde_json::to_string(&send_msg).unwrap();
-        self.send_boardcast(&send_str.as_str(), send_msg.from.unwrap());
+        let filtered = match self.filter(id, user_id, Target::Broadcast, &msg) {
+            Some(filtered) => filtered,
+            None => return Box::new(fut::ok(())),
+        };
+        let stored = self.store(&span, move |s, search| {
+            check_sanction(s, None, user_id, SanctionKind::Mute)?;
+            let r = s.add_broadcast_message(user_id, &filtered.content);
+            let r = persist(s, search, r, &filtered.flags);
+            Ok((filtered.content, r.ok().map(|m| m.message_id)))
+        });
+        let span = span.clone();
+        Box::new(stored.map(move |r, act, ctx| {
+            let _enter = span.enter();
+            match checked(r) {
+                Ok((content, persisted)) => act.broadcast(content, id, persisted, ctx),
+                Err(refused) => act.refuse(id, refused),
+            }
+            Ok(())
+        }))
+    }
+}
+
+impl Handler<Announce> for ChatServer {
+    type Result = ResponseActFuture<Self, Result<QueryMessage, Error>>;
+    fn handle(&mut self, msg: Announce, _: &mut Self::Context) -> Self::Result {
+        let Announce { user_id, msg } = msg;
+        let content = msg.clone();
+        let stored = self.store(&Span::current(), move |s, search| {
+            persist(s, search, s.add_broadcast_message(user_id, &msg), &[])
+        });
+        Box::new(stored.map(move |r, act, ctx| {
+            let r = r.and_then(|r| r);
+            act.broadcast(content, 0, r.as_ref().ok().map(|m| m.message_id), ctx);
+            r
+        }))
+    }
+}
+
+impl Handler<Notify> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: Notify, _: &mut Self::Context) {
+        let text = serde_json::to_string(&msg.msg).unwrap();
+        for id in self.sessions_of(msg.user_id) {
+            self.send_p2p_message(&id, text.as_str());
+        }
+    }
+}
+
+impl Handler<OnlineUsers> for ChatServer {
+    type Result = MessageResult<OnlineUsers>;
+
+    fn handle(&mut self, msg: OnlineUsers, _: &mut Self::Context) -> Self::Result {
+        let wanted: HashSet<i32> = msg.users.into_iter().collect();
+        let online = self
+            .sessions
+            .values()
+            .map(|s| s.user_id)
+            .filter(|id| wanted.contains(id))
+            .collect();
+        MessageResult(online)
     }
 }
 
@@ -192,29 +816,309 @@ impl Handler<ListRooms> for ChatServer {
 
     fn handle(&mut self, _: ListRooms, _: &mut Self::Context) -> Self::Result {
         let mut rooms = Vec::new();
-        for key in self.rooms.keys() {
-            rooms.push(key.to_owned());
+        for (name, sessions) in &self.rooms {
+            let users: HashSet<i32> = sessions.iter().filter_map(|id| self.user_of(*id)).collect();
+            rooms.push(RoomInfo {
+                name: name.to_owned(),
+                sessions: sessions.len(),
+                users: users.len(),
+                messages: self.room_messages.get(name).cloned().unwrap_or(0),
+            });
         }
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
         MessageResult(rooms)
     }
 }
 
+impl Handler<ListSessions> for ChatServer {
+    type Result = MessageResult<ListSessions>;
+
+    fn handle(&mut self, _: ListSessions, _: &mut Self::Context) -> Self::Result {
+        let mut sessions: Vec<SessionInfo> = self
+            .sessions
+            .iter()
+            .map(|(id, s)| SessionInfo {
+                session_id: id.to_string(),
+                user_id: s.user_id,
+                connected_at: s.connected_at,
+                remote_addr: s.remote_addr.clone(),
+                rooms: self
+                    .rooms
+                    .iter()
+                    .filter(|(_, ids)| ids.contains(id))
+                    .map(|(name, _)| name.to_owned())
+                    .collect(),
+            })
+            .collect();
+        sessions.sort_by_key(|s| s.connected_at);
+        MessageResult(sessions)
+    }
+}
+
+impl Handler<CloseSession> for ChatServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: CloseSession, _: &mut Self::Context) -> Self::Result {
+        match self.sessions.get(&msg.id) {
+            Some(session) => {
+                let _ = session.kick.do_send(Kick {
+                    code: CloseCode::Policy,
+                    reason: msg.reason,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Handler<GetThroughput> for ChatServer {
+    type Result = MessageResult<GetThroughput>;
+
+    fn handle(&mut self, _: GetThroughput, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.throughput.stats())
+    }
+}
+
 impl Handler<Join> for ChatServer {
-    type Result = ();
-    fn handle(&mut self, msg: Join, _: &mut Self::Context) {
-        let Join { id, name } = msg;
-        let mut rooms = Vec::new();
+    type Result = ResponseActFuture<Self, Result<(), ()>>;
+    fn handle(&mut self, msg: Join, _: &mut Self::Context) -> Self::Result {
+        let Join { id, name, span } = msg;
+        let span = info_span!(parent: &span, "route", kind = "join", room = %name);
+        let user_id = match self.user_of(id) {
+            Some(user_id) => user_id,
+            None => return Box::new(fut::ok(())),
+        };
+        let room = name.clone();
+        let stored = self.store(&span, move |s, _| {
+            check_sanction(s, Some(&room), user_id, SanctionKind::Ban)?;
+            if let Err(e) = s.join_room(user_id, &room) {
+                error!(error = ?e, "record room member failed");
+            }
+            Ok(())
+        });
+        Box::new(stored.map(move |r, act, _| {
+            let _enter = span.enter();
+            if let Err(refused) = checked(r) {
+                act.refuse(id, refused);
+                return Ok(());
+            }
+            // 会话已断开
+            if !act.sessions.contains_key(&id) {
+                return Ok(());
+            }
+            let mut rooms = Vec::new();
+
+            for (n, sessions) in &mut act.rooms {
+                if sessions.remove(&id) {
+                    rooms.push(n.to_owned());
+                }
+            }
 
-        for (n, sessions) in &mut self.rooms {
-            if sessions.remove(&id) {
-                rooms.push(n.to_owned());
+            if act.rooms.get_mut(&name).is_none() {
+                act.rooms.insert(name.clone(), HashSet::new());
             }
+            // self.send_message(&name, "Someone connect", id);
+            act.rooms.get_mut(&name).unwrap().insert(id);
+            act.update_gauges();
+            act.routed("join");
+            Ok(())
+        }))
+    }
+}
+
+impl Handler<Moderate> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: Moderate, _: &mut Self::Context) {
+        let Moderate {
+            room,
+            user_id,
+            sanction,
+            notice,
+        } = msg;
+        let targets = self.sessions_of(user_id);
+        match room {
+            Some(room) => {
+                if sanction == Sanction::Kick || sanction == Sanction::Ban {
+                    for id in &targets {
+                        let removed = self
+                            .rooms
+                            .get_mut(&room)
+                            .is_some_and(|sessions| sessions.remove(id));
+                        if removed {
+                            let text = Self::notice(Some(&room), notice.as_str());
+                            self.send_p2p_message(id, text.as_str());
+                        }
+                    }
+                }
+                let text = Self::notice(Some(&room), notice.as_str());
+                self.send_message(&room, text.as_str(), 0);
+                self.update_gauges();
+            }
+            None => {
+                for id in &targets {
+                    if sanction == Sanction::Kick || sanction == Sanction::Ban {
+                        if let Some(session) = self.sessions.get(id) {
+                            let _ = session.kick.do_send(Kick {
+                                code: CloseCode::Policy,
+                                reason: notice.clone(),
+                            });
+                        }
+                    } else {
+                        let text = Self::notice(None, notice.as_str());
+                        self.send_p2p_message(id, text.as_str());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Handler<Ping> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _: Ping, _: &mut Self::Context) {}
+}
+
+impl Handler<Pending> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, _: Pending, _: &mut Self::Context) -> Self::Result {
+        self.in_flight
+    }
+}
+
+impl Handler<Shutdown> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Shutdown, _: &mut Self::Context) -> Self::Result {
+        self.going_away = Some(msg.reconnect_after);
+        let ids: Vec<usize> = self.sessions.keys().cloned().collect();
+        for id in &ids {
+            self.send_going_away(*id, msg.reconnect_after);
         }
+        ids.len()
+    }
+}
 
-        if self.rooms.get_mut(&name).is_none() {
-            self.rooms.insert(name.clone(), HashSet::new());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::filter::FilterConfig;
+    use crate::db::memory::MemoryStorage;
+    use std::sync::Mutex;
+
+    // 记下收到的帧, 代替 websocket 会话
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Actor for Recorder {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Message> for Recorder {
+        type Result = ();
+        fn handle(&mut self, msg: Message, _: &mut Self::Context) {
+            self.0.lock().unwrap().push(msg.text);
         }
-        // self.send_message(&name, "Someone connect", id);
-        self.rooms.get_mut(&name).unwrap().insert(id);
+    }
+
+    impl Handler<Kick> for Recorder {
+        type Result = ();
+        fn handle(&mut self, _: Kick, _: &mut Self::Context) {}
+    }
+
+    // 邮箱按顺序处理, 回复时之前投递的帧都已收到
+    #[derive(Message)]
+    #[rtype(result = "Vec<String>")]
+    struct Take;
+
+    impl Handler<Take> for Recorder {
+        type Result = MessageResult<Take>;
+        fn handle(&mut self, _: Take, _: &mut Self::Context) -> Self::Result {
+            MessageResult(self.0.lock().unwrap().drain(..).collect())
+        }
+    }
+
+    async fn connect(srv: &Addr<ChatServer>, user_id: i32) -> (usize, Addr<Recorder>) {
+        let recorder = Recorder(Arc::new(Mutex::new(Vec::new()))).start();
+        let id = srv
+            .send(Connect {
+                addr: recorder.clone().recipient(),
+                kick: recorder.clone().recipient(),
+                user_id,
+                remote_addr: None,
+            })
+            .await
+            .unwrap();
+        (id, recorder)
+    }
+
+    fn say(id: usize, room: &str, msg: &str) -> RoomMessage {
+        RoomMessage {
+            id,
+            msg: msg.to_owned(),
+            room: room.to_owned(),
+            span: Span::none(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn room_messages_need_membership() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut users = Vec::new();
+        for name in &["alice", "bob", "carol"] {
+            storage
+                .add_user(name.to_string(), "passwd".to_owned())
+                .unwrap();
+            users.push(storage.find_user_by_name(name).unwrap().user_id);
+        }
+        let srv = ChatServer::new(
+            Repository::new(storage.clone()),
+            storage.clone(),
+            FilterChain::from_config(FilterConfig::default()),
+        )
+        .start();
+        let (alice, alice_log) = connect(&srv, users[0]).await;
+        let (bob, bob_log) = connect(&srv, users[1]).await;
+        let (carol, carol_log) = connect(&srv, users[2]).await;
+        for id in &[alice, bob] {
+            let join = Join {
+                id: *id,
+                name: "rust".to_owned(),
+                span: Span::none(),
+            };
+            srv.send(join).await.unwrap().unwrap();
+        }
+        for log in &[&alice_log, &bob_log, &carol_log] {
+            log.send(Take).await.unwrap();
+        }
+
+        srv.send(say(alice, "rust", "hello"))
+            .await
+            .unwrap()
+            .unwrap();
+        let received = bob_log.send(Take).await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].contains("hello"));
+
+        // 没有加入房间
+        srv.send(say(carol, "rust", "hi")).await.unwrap().unwrap();
+        let refused = carol_log.send(Take).await.unwrap();
+        assert_eq!(refused.len(), 1);
+        assert!(refused[0].contains("not in the room"));
+        assert!(bob_log.send(Take).await.unwrap().is_empty());
+
+        // 加入后被封禁
+        storage
+            .add_sanction(Some("rust"), users[0], SanctionKind::Ban, None)
+            .unwrap();
+        srv.send(say(alice, "rust", "again"))
+            .await
+            .unwrap()
+            .unwrap();
+        let refused = alice_log.send(Take).await.unwrap();
+        assert_eq!(refused.len(), 1);
+        assert!(refused[0].contains("banned"));
+        assert!(bob_log.send(Take).await.unwrap().is_empty());
     }
 }
@@ -0,0 +1,834 @@
+use crate::chat::filter::FilterConfig;
+use crate::chat::limit::{Bucket, Limit, RateLimitConfig};
+use crate::chat::model::ChatMessageType;
+use crate::db::account::{AccountConfig, MessagePolicy};
+use crate::db::storage::StorageKind;
+use crate::mail;
+use crate::tls;
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+// 未指定 `--config` 或 `CONFIG` 时, 当前目录下有这个文件就读取
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+// 服务的配置, 优先级从低到高: 默认值, TOML 配置文件, 环境变量, 命令行参数.
+// `load` 会校验结果, 其余代码可以直接使用
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub cors: CorsConfig,
+    pub database: DatabaseConfig,
+    pub redis: RedisConfig,
+    pub websocket: WebsocketConfig,
+    pub features: Features,
+    pub tracing: TracingConfig,
+    pub rate_limit: RateLimitConfig,
+    pub filter: FilterConfig,
+    pub account: AccountConfig,
+    pub mail: MailConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // `BIND`, 逗号分隔
+    pub bind: Vec<String>,
+    // http worker 线程数, 0 为 CPU 核数
+    pub workers: usize,
+    // `RUST_LOG` 格式
+    pub log: String,
+    // `LOG_FORMAT`
+    pub log_format: LogFormat,
+    // json 请求体的上限, 字节
+    pub json_limit: usize,
+    // 收到 SIGTERM/SIGINT 后关闭连接、等待请求完成的最长时间, 秒
+    #[serde(with = "seconds")]
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind: vec!["127.0.0.1:8080".to_owned()],
+            workers: 0,
+            log: "info,actix_web=debug".to_owned(),
+            log_format: LogFormat::Json,
+            json_limit: 32 * 1024,
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // 一行一个 json 对象, 带所在 span 的字段
+    Json,
+    // 便于本地阅读
+    Text,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Option<LogFormat> {
+        match value {
+            "json" => Some(LogFormat::Json),
+            "text" => Some(LogFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // `STORAGE`
+    pub storage: StorageKind,
+    // `DATABASE_URL`, `storage = "sql"` 时必填
+    pub url: Option<String>,
+    pub pool_size: u32,
+    // `RUN_MIGRATIONS`, 启动前执行未执行的迁移
+    pub run_migrations: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            storage: StorageKind::Sql,
+            url: None,
+            pool_size: 10,
+            run_migrations: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    // `REDIS_URL`, 必填
+    pub url: Option<String>,
+    pub pool_size: u32,
+}
+
+impl Default for RedisConfig {
+    fn default() -> RedisConfig {
+        RedisConfig {
+            url: None,
+            pool_size: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebsocketConfig {
+    // 每隔多久 ping 一次客户端, 秒
+    #[serde(with = "seconds")]
+    pub heartbeat_interval: Duration,
+    // 超过这个时间没有收到 pong 就断开, 秒
+    #[serde(with = "seconds")]
+    pub client_timeout: Duration,
+    // 关闭服务时告诉客户端多久之后重连, 秒
+    #[serde(with = "seconds")]
+    pub reconnect_after: Duration,
+    // 单个帧的最大字节数, 超过时以 1009 关闭连接
+    pub max_frame_size: usize,
+    pub max_content_length: ContentLimits,
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> WebsocketConfig {
+        WebsocketConfig {
+            heartbeat_interval: Duration::from_secs(5),
+            client_timeout: Duration::from_secs(10),
+            reconnect_after: Duration::from_secs(5),
+            max_frame_size: 64 * 1024,
+            max_content_length: ContentLimits::default(),
+        }
+    }
+}
+
+// 各类消息 `content` 的最大字符数, 没有设置的类型用 `filter.max_length`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentLimits {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_to_one: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broadcast: Option<usize>,
+}
+
+impl ContentLimits {
+    // 消息类型对应的名称和上限, 没有 content 的类型返回 None
+    pub fn of(&self, style: &ChatMessageType, default: usize) -> Option<(&'static str, usize)> {
+        let (name, limit) = match style {
+            ChatMessageType::RoomMessage(_) => ("room", self.room),
+            ChatMessageType::OneToOne(_) => ("one_to_one", self.one_to_one),
+            ChatMessageType::Broadcast => ("broadcast", self.broadcast),
+            _ => return None,
+        };
+        Some((name, limit.unwrap_or(default)))
+    }
+}
+
+// 关闭后对应的接口不注册, 返回 404
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    // POST /api/signup
+    pub registration: bool,
+    // GET /api/search
+    pub search: bool,
+    // POST /api/password/forgot, /api/password/reset
+    pub password_reset: bool,
+    // GET /metrics, prometheus 格式
+    pub metrics: bool,
+}
+
+impl Default for Features {
+    fn default() -> Features {
+        Features {
+            registration: true,
+            search: true,
+            password_reset: true,
+            metrics: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    // `TLS_BIND`, 逗号分隔; 设置了 cert 和 key 时在这些地址上提供 https/wss
+    pub bind: Vec<String>,
+    // `TLS_CERT`, PEM 格式的证书链
+    pub cert: Option<String>,
+    // `TLS_KEY`, PEM 格式的私钥 (PKCS#8 或 RSA)
+    pub key: Option<String>,
+    // 检查证书文件是否更新的间隔, 秒
+    #[serde(with = "seconds")]
+    pub reload_interval: Duration,
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig {
+            bind: vec!["127.0.0.1:8443".to_owned()],
+            cert: None,
+            key: None,
+            reload_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn enabled(&self) -> bool {
+        self.cert.is_some() && self.key.is_some()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // `CORS_ALLOWED_ORIGINS`, 逗号分隔, 如 `https://chat.example.com`; `*` 为任意来源.
+    // 同时用于 `/api` 的 CORS 和 `/ws` 握手的 Origin 检查
+    pub allowed_origins: Vec<String>,
+    // 预检结果的缓存时间, 秒
+    #[serde(with = "seconds")]
+    pub max_age: Duration,
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            max_age: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|o| o == "*" || o.eq_ignore_ascii_case(origin))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    // `OTEL_EXPORTER_OTLP_ENDPOINT`, OTLP/HTTP collector 地址, 如 `http://localhost:4318`;
+    // 不设置时不导出 span
+    pub otlp_endpoint: Option<String>,
+    // `OTEL_SERVICE_NAME`
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> TracingConfig {
+        TracingConfig {
+            otlp_endpoint: None,
+            service_name: env!("CARGO_PKG_NAME").to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    // `MAILER`: `log` 或 `file:<path>`
+    pub mailer: String,
+    // `PASSWORD_RESET_URL`, 前端的重置页面, 不设置时只发送 token
+    pub password_reset_url: Option<String>,
+}
+
+impl Default for MailConfig {
+    fn default() -> MailConfig {
+        MailConfig {
+            mailer: "log".to_owned(),
+            password_reset_url: None,
+        }
+    }
+}
+
+// 配置中的所有错误, 每行一个
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for e in &self.0 {
+            writeln!(f, "  - {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // 读取 `--config` 或 `CONFIG` 指定的配置文件 (没有时读取存在的 `config.toml`),
+    // 再应用环境变量和 `args` 中的参数
+    pub fn load(args: &ArgMatches, needs_redis: bool) -> Result<Config, ConfigError> {
+        let mut errors = Vec::new();
+        let path = args
+            .value_of("config")
+            .map(|p| p.to_owned())
+            .or_else(|| env::var("CONFIG").ok());
+        let mut config = match path {
+            Some(ref path) => Config::from_file(path).map_err(|e| ConfigError(vec![e]))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(DEFAULT_CONFIG_FILE).map_err(|e| ConfigError(vec![e]))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env(|k| env::var(k), &mut errors);
+        config.apply_args(args, &mut errors);
+        config.validate(needs_redis, &mut errors);
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(errors))
+        }
+    }
+
+    fn from_file(path: &str) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // `var` 查环境变量, 测试时换成固定的表
+    fn apply_env<F>(&mut self, var: F, errors: &mut Vec<String>)
+    where
+        F: Fn(&str) -> Result<String, env::VarError>,
+    {
+        if let Ok(v) = var("BIND") {
+            self.server.bind = v.split(',').map(|a| a.trim().to_owned()).collect();
+        }
+        if let Ok(v) = var("TLS_BIND") {
+            self.tls.bind = v.split(',').map(|a| a.trim().to_owned()).collect();
+        }
+        if let Ok(v) = var("TLS_CERT") {
+            self.tls.cert = Some(v);
+        }
+        if let Ok(v) = var("TLS_KEY") {
+            self.tls.key = Some(v);
+        }
+        if let Ok(v) = var("RUST_LOG") {
+            self.server.log = v;
+        }
+        if let Ok(v) = var("LOG_FORMAT") {
+            match LogFormat::parse(v.trim()) {
+                Some(format) => self.server.log_format = format,
+                None => errors.push(format!("`LOG_FORMAT={}`: expected json or text", v)),
+            }
+        }
+        if let Ok(v) = var("STORAGE") {
+            match StorageKind::parse(v.trim()) {
+                Some(kind) => self.database.storage = kind,
+                None => errors.push(format!("`STORAGE={}`: expected sql or memory", v)),
+            }
+        }
+        if let Ok(v) = var("DATABASE_URL") {
+            self.database.url = Some(v);
+        }
+        if let Ok(v) = var("RUN_MIGRATIONS") {
+            match v.trim() {
+                "true" | "1" => self.database.run_migrations = true,
+                "false" | "0" => self.database.run_migrations = false,
+                _ => errors.push(format!("`RUN_MIGRATIONS={}`: expected true or false", v)),
+            }
+        }
+        if let Ok(v) = var("REDIS_URL") {
+            self.redis.url = Some(v);
+        }
+        if let Ok(v) = var("WS_MAX_FRAME_SIZE") {
+            match v.trim().parse() {
+                Ok(n) => self.websocket.max_frame_size = n,
+                Err(_) => errors.push(format!("`WS_MAX_FRAME_SIZE={}`: not a number", v)),
+            }
+        }
+
+        // `RATE_LIMIT_<NAME>=<容量>:<每秒补充数>`
+        let buckets = Limit::ALL
+            .iter()
+            .map(|l| (l.name().to_ascii_uppercase(), Some(*l)))
+            .chain(Some(("CONNECTION".to_owned(), None)));
+        for (name, limit) in buckets {
+            let key = format!("RATE_LIMIT_{}", name);
+            if let Ok(v) = var(&key) {
+                match Bucket::parse(&v) {
+                    Some(b) => match limit {
+                        Some(l) => *self.rate_limit.bucket_mut(l) = b,
+                        None => self.rate_limit.connection = b,
+                    },
+                    None => errors.push(format!("`{}={}`: expected <capacity>:<refill>", key, v)),
+                }
+            }
+        }
+        if let Ok(v) = var("RATE_LIMIT_MAX_VIOLATIONS") {
+            match v.trim().parse() {
+                Ok(n) => self.rate_limit.max_violations = n,
+                Err(_) => errors.push(format!("`RATE_LIMIT_MAX_VIOLATIONS={}`: not a number", v)),
+            }
+        }
+        if let Ok(v) = var("RATE_LIMIT_BACKEND") {
+            match v.trim() {
+                "redis" => self.rate_limit.use_redis = true,
+                "local" => self.rate_limit.use_redis = false,
+                _ => errors.push(format!(
+                    "`RATE_LIMIT_BACKEND={}`: expected local or redis",
+                    v
+                )),
+            }
+        }
+
+        // 替换整个 [filter]
+        if let Ok(path) = var("FILTER_CONFIG") {
+            match FilterConfig::from_file(&path) {
+                Ok(filter) => self.filter = filter,
+                Err(e) => errors.push(format!("`FILTER_CONFIG` {}", e)),
+            }
+        }
+
+        if let Ok(v) = var("ACCOUNT_RESTORE_DAYS") {
+            match v.trim().parse() {
+                Ok(days) => self.account.restore_days = days,
+                Err(_) => errors.push(format!("`ACCOUNT_RESTORE_DAYS={}`: not a number", v)),
+            }
+        }
+        if let Ok(v) = var("DELETED_MESSAGES") {
+            match MessagePolicy::parse(v.trim()) {
+                Some(p) => self.account.messages = p,
+                None => errors.push(format!(
+                    "`DELETED_MESSAGES={}`: expected keep, redact or erase",
+                    v
+                )),
+            }
+        }
+
+        if let Ok(v) = var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            self.tracing.otlp_endpoint = Some(v);
+        }
+        if let Ok(v) = var("OTEL_SERVICE_NAME") {
+            self.tracing.service_name = v;
+        }
+        if let Ok(v) = var("CORS_ALLOWED_ORIGINS") {
+            self.cors.allowed_origins = v
+                .split(',')
+                .map(|o| o.trim().to_owned())
+                .filter(|o| !o.is_empty())
+                .collect();
+        }
+
+        if let Ok(v) = var("MAILER") {
+            self.mail.mailer = v;
+        }
+        if let Ok(v) = var("PASSWORD_RESET_URL") {
+            self.mail.password_reset_url = Some(v);
+        }
+    }
+
+    fn apply_args(&mut self, args: &ArgMatches, errors: &mut Vec<String>) {
+        if let Some(v) = args.values_of("bind") {
+            self.server.bind = v.map(|a| a.to_owned()).collect();
+        }
+        if let Some(v) = args.value_of("log") {
+            self.server.log = v.to_owned();
+        }
+        if let Some(v) = args.value_of("log-format") {
+            match LogFormat::parse(v) {
+                Some(format) => self.server.log_format = format,
+                None => errors.push(format!("`--log-format {}`: expected json or text", v)),
+            }
+        }
+        if let Some(v) = args.value_of("storage") {
+            match StorageKind::parse(v) {
+                Some(kind) => self.database.storage = kind,
+                None => errors.push(format!("`--storage {}`: expected sql or memory", v)),
+            }
+        }
+        if let Some(v) = args.value_of("database-url") {
+            self.database.url = Some(v.to_owned());
+        }
+        if let Some(v) = args.value_of("redis-url") {
+            self.redis.url = Some(v.to_owned());
+        }
+        if args.is_present("run-migrations") {
+            self.database.run_migrations = true;
+        }
+    }
+
+    // `needs_redis` 为 false 的命令不连接 redis, 不要求 `redis.url`
+    fn validate(&self, needs_redis: bool, errors: &mut Vec<String>) {
+        // 启用 TLS 时可以只监听 tls.bind
+        if self.server.bind.is_empty() && !self.tls.enabled() {
+            errors.push("server.bind: at least one address is required".to_owned());
+        }
+        for addr in &self.server.bind {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(format!("server.bind: `{}` is not a socket address", addr));
+            }
+        }
+        match (&self.tls.cert, &self.tls.key) {
+            (Some(cert), Some(key)) => {
+                if self.tls.bind.is_empty() {
+                    errors.push("tls.bind: at least one address is required".to_owned());
+                }
+                for addr in &self.tls.bind {
+                    if addr.parse::<SocketAddr>().is_err() {
+                        errors.push(format!("tls.bind: `{}` is not a socket address", addr));
+                    }
+                }
+                if let Err(e) = tls::load(cert, key) {
+                    errors.push(format!("tls: {}", e));
+                }
+                if self.tls.reload_interval.as_secs() == 0 {
+                    errors.push("tls.reload_interval: must be positive".to_owned());
+                }
+            }
+            (None, None) => (),
+            _ => errors.push("tls.cert and tls.key: set both or neither".to_owned()),
+        }
+        if self.server.shutdown_timeout.as_secs() == 0 {
+            errors.push("server.shutdown_timeout: must be positive".to_owned());
+        }
+        if self.server.json_limit == 0 {
+            errors.push("server.json_limit: must be positive".to_owned());
+        }
+
+        #[cfg(not(feature = "sql"))]
+        {
+            if self.database.storage == StorageKind::Sql {
+                errors.push(
+                    "database.storage: sql needs a build with the mysql or postgres feature"
+                        .to_owned(),
+                );
+            }
+        }
+        if self.database.storage == StorageKind::Sql && self.database.url.is_none() {
+            errors.push("database.url (`DATABASE_URL`): required with storage sql".to_owned());
+        }
+        if self.database.pool_size == 0 {
+            errors.push("database.pool_size: must be positive".to_owned());
+        }
+        if needs_redis && self.redis.url.is_none() {
+            errors.push("redis.url (`REDIS_URL`): required".to_owned());
+        }
+        if self.redis.pool_size == 0 {
+            errors.push("redis.pool_size: must be positive".to_owned());
+        }
+
+        let ws = &self.websocket;
+        if ws.heartbeat_interval.as_secs() == 0 {
+            errors.push("websocket.heartbeat_interval: must be positive".to_owned());
+        }
+        if ws.client_timeout <= ws.heartbeat_interval {
+            errors.push(
+                "websocket.client_timeout: must be longer than heartbeat_interval".to_owned(),
+            );
+        }
+        if ws.max_frame_size == 0 {
+            errors.push("websocket.max_frame_size: must be positive".to_owned());
+        }
+        let content = &ws.max_content_length;
+        let limits = [
+            ("room", content.room),
+            ("one_to_one", content.one_to_one),
+            ("broadcast", content.broadcast),
+        ];
+        for (name, limit) in limits.iter() {
+            match limit {
+                Some(0) => errors.push(format!(
+                    "websocket.max_content_length.{}: must be positive",
+                    name
+                )),
+                Some(l) if *l > ws.max_frame_size => errors.push(format!(
+                    "websocket.max_content_length.{}: must not exceed max_frame_size",
+                    name
+                )),
+                _ => (),
+            }
+        }
+
+        let buckets = Limit::ALL
+            .iter()
+            .map(|l| (l.name(), self.rate_limit.bucket(*l)))
+            .chain(Some(("connection", &self.rate_limit.connection)));
+        for (name, bucket) in buckets {
+            if !bucket.is_valid() {
+                errors.push(format!(
+                    "rate_limit.{}: capacity must be at least 1, refill positive, both finite and refilling within 7 days",
+                    name
+                ));
+            }
+        }
+        if self.rate_limit.violation_window.as_secs() == 0 {
+            errors.push("rate_limit.violation_window: must be positive".to_owned());
+        }
+
+        if self.filter.max_length == 0 {
+            errors.push("filter.max_length: must be positive".to_owned());
+        } else if self.filter.max_length > ws.max_frame_size {
+            errors.push("filter.max_length: must not exceed websocket.max_frame_size".to_owned());
+        }
+        if self.account.restore_days < 0 {
+            errors.push("account.restore_days: must not be negative".to_owned());
+        }
+        // Origin 头只有 scheme://host[:port], 不带路径
+        for origin in &self.cors.allowed_origins {
+            let host = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"));
+            let valid = origin == "*" || host.is_some_and(|h| !h.is_empty() && !h.contains('/'));
+            if !valid {
+                errors.push(format!(
+                    "cors.allowed_origins: `{}`, expected `*` or scheme://host[:port]",
+                    origin
+                ));
+            }
+        }
+        if let Some(ref endpoint) = self.tracing.otlp_endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                errors.push(format!(
+                    "tracing.otlp_endpoint: `{}`, expected an http(s) url",
+                    endpoint
+                ));
+            }
+        }
+        if !mail::is_valid(&self.mail.mailer) {
+            errors.push(format!(
+                "mail.mailer: `{}`, expected log or file:<path>",
+                self.mail.mailer
+            ));
+        }
+    }
+}
+
+// Duration 在配置文件中写作秒数
+pub mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(d.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        u64::deserialize(d).map(Duration::from_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::filter::too_long;
+    use std::collections::HashMap;
+
+    fn errors(config: &Config) -> Vec<String> {
+        let mut errors = Vec::new();
+        config.validate(true, &mut errors);
+        errors
+    }
+
+    // 用 `vars` 代替环境变量, 返回解析出错的变量
+    fn apply(config: &mut Config, vars: &[(&str, &str)]) -> Vec<String> {
+        let vars: HashMap<_, _> = vars.iter().cloned().collect();
+        let mut errors = Vec::new();
+        config.apply_env(
+            |k| {
+                vars.get(k)
+                    .map(|v| v.to_string())
+                    .ok_or(env::VarError::NotPresent)
+            },
+            &mut errors,
+        );
+        errors
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let mut config: Config = toml::from_str(
+            "[database]\nurl = \"mysql://file\"\nrun_migrations = true\npool_size = 3\n\n[redis]\nurl = \"redis://file\"\n\n[rate_limit]\nmax_violations = 7\n",
+        )
+        .unwrap();
+        let errors = apply(
+            &mut config,
+            &[
+                ("DATABASE_URL", "mysql://env"),
+                ("RUN_MIGRATIONS", "0"),
+                ("RATE_LIMIT_ROOM", "3:0.5"),
+                ("BIND", "127.0.0.1:1, 127.0.0.1:2"),
+            ],
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.database.url.as_deref(), Some("mysql://env"));
+        assert!(!config.database.run_migrations);
+        assert_eq!(config.rate_limit.room.capacity, 3.0);
+        assert_eq!(config.server.bind, vec!["127.0.0.1:1", "127.0.0.1:2"]);
+        // 没有设置的变量保留文件中的值
+        assert_eq!(config.database.pool_size, 3);
+        assert_eq!(config.redis.url.as_deref(), Some("redis://file"));
+        assert_eq!(config.rate_limit.max_violations, 7);
+
+        let errors = apply(&mut config, &[("RUN_MIGRATIONS", "1")]);
+        assert!(errors.is_empty());
+        assert!(config.database.run_migrations);
+    }
+
+    #[test]
+    fn invalid_env_values_are_reported() {
+        let mut config = Config::default();
+        let errors = apply(
+            &mut config,
+            &[
+                ("RUN_MIGRATIONS", "yes"),
+                ("STORAGE", "disk"),
+                ("WS_MAX_FRAME_SIZE", "big"),
+                ("RATE_LIMIT_JOIN", "5"),
+            ],
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "`STORAGE=disk`: expected sql or memory",
+                "`RUN_MIGRATIONS=yes`: expected true or false",
+                "`WS_MAX_FRAME_SIZE=big`: not a number",
+                "`RATE_LIMIT_JOIN=5`: expected <capacity>:<refill>",
+            ]
+        );
+        assert!(!config.database.run_migrations);
+    }
+
+    #[test]
+    fn validation_collects_every_error() {
+        let mut config = Config::default();
+        config.database.storage = StorageKind::Sql;
+        config.database.url = None;
+        config.websocket.client_timeout = config.websocket.heartbeat_interval;
+        config.filter.max_length = 0;
+        let errors = errors(&config);
+        for expected in &[
+            "database.url (`DATABASE_URL`): required with storage sql",
+            "websocket.client_timeout: must be longer than heartbeat_interval",
+            "filter.max_length: must be positive",
+        ] {
+            assert!(errors.iter().any(|e| e == expected), "{:?}", errors);
+        }
+    }
+
+    #[cfg(not(feature = "sql"))]
+    #[test]
+    fn sql_storage_needs_a_database_feature() {
+        let mut config = Config::default();
+        config.database.url = Some("mysql://db/chat".to_owned());
+        assert!(errors(&config).contains(
+            &"database.storage: sql needs a build with the mysql or postgres feature".to_owned()
+        ));
+        config.database.storage = StorageKind::Memory;
+        assert!(!errors(&config).iter().any(|e| e.starts_with("database")));
+    }
+
+    #[test]
+    fn redis_url_only_required_when_used() {
+        let redis = "redis.url (`REDIS_URL`): required".to_owned();
+        let mut config = Config::default();
+        config.database.storage = StorageKind::Memory;
+        assert!(errors(&config).contains(&redis));
+        let mut errors = Vec::new();
+        config.validate(false, &mut errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn content_limits_per_type() {
+        let config: Config = toml::from_str(
+            "[websocket.max_content_length]\nroom = 10\none_to_one = 20\n\n[filter]\nmax_length = 30\n",
+        )
+        .unwrap();
+        let limits = config.websocket.max_content_length;
+        let styles = [
+            (ChatMessageType::RoomMessage("rust".to_owned()), "room", 10),
+            (ChatMessageType::OneToOne(1), "one_to_one", 20),
+            // 没有设置时用 filter.max_length
+            (ChatMessageType::Broadcast, "broadcast", 30),
+        ];
+        for (style, name, limit) in styles.iter() {
+            assert_eq!(
+                limits.of(style, config.filter.max_length),
+                Some((*name, *limit))
+            );
+            assert_eq!(too_long(&"a".repeat(*limit), *limit), None);
+            assert!(too_long(&"a".repeat(limit + 1), *limit).is_some());
+        }
+        assert_eq!(
+            limits.of(&ChatMessageType::Join("rust".to_owned()), 30),
+            None
+        );
+    }
+
+    #[test]
+    fn content_limits_are_validated() {
+        let mut config = Config::default();
+        config.websocket.max_frame_size = 100;
+        config.filter.max_length = 100;
+        config.websocket.max_content_length.room = Some(0);
+        config.websocket.max_content_length.broadcast = Some(101);
+        let errors = errors(&config);
+        assert!(errors.contains(&"websocket.max_content_length.room: must be positive".to_owned()));
+        assert!(errors.contains(
+            &"websocket.max_content_length.broadcast: must not exceed max_frame_size".to_owned()
+        ));
+        assert!(!errors.iter().any(|e| e.contains("one_to_one")));
+    }
+}
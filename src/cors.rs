@@ -0,0 +1,208 @@
+use crate::config::Config;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, HeaderMap, HeaderValue, Method};
+use actix_web::{Error, HttpRequest, HttpResponse};
+use std::future::Future;
+use std::pin::Pin;
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE";
+const ALLOWED_HEADERS: &str = "authorization, content-type";
+// `write_400` 把错误原因放在 Warning 头中
+const EXPOSED_HEADERS: &str = "warning, retry-after";
+
+fn origin_of(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned())
+}
+
+// Origin 与请求的 Host 相同, 即页面和服务在同一个来源
+fn same_origin(req: &HttpRequest, origin: &str) -> bool {
+    let host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"));
+    host.is_some_and(|h| h.eq_ignore_ascii_case(req.connection_info().host()))
+}
+
+// `/ws` 握手的 Origin 检查: 浏览器总会带 Origin, 只接受同源和 `cors.allowed_origins`
+// 中的来源, 防止其他站点的页面借用户的登录态连接 (cross-site WebSocket hijacking).
+// 没有 Origin 的非浏览器客户端不受限制
+pub fn allows_websocket(config: &Config, req: &HttpRequest) -> bool {
+    match origin_of(req.headers()) {
+        Some(origin) => same_origin(req, &origin) || config.cors.allows(&origin),
+        None => true,
+    }
+}
+
+// `/api` 的 CORS: 允许的来源回答预检并加上 `Access-Control-Allow-*`, 其他来源不加任何头
+pub fn handle<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> Pin<Box<dyn Future<Output = Result<ServiceResponse, Error>>>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    let (origin, max_age) = match (origin_of(req.headers()), req.app_data::<Config>()) {
+        (Some(origin), Some(config)) if config.cors.allows(&origin) => {
+            (origin, config.cors.max_age.as_secs())
+        }
+        _ => return Box::pin(srv.call(req)),
+    };
+    let allow_origin = match HeaderValue::from_str(&origin) {
+        Ok(v) => v,
+        Err(_) => return Box::pin(srv.call(req)),
+    };
+
+    let preflight = req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if preflight {
+        let res = HttpResponse::NoContent()
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS)
+            .header(header::ACCESS_CONTROL_MAX_AGE, max_age.to_string())
+            .header(header::VARY, "Origin")
+            .finish();
+        return Box::pin(async move { Ok(req.into_response(res)) });
+    }
+
+    let fut = srv.call(req);
+    Box::pin(async move {
+        let mut res = fut.await?;
+        let headers = res.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSED_HEADERS),
+        );
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        Ok(res)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    const ORIGIN: &str = "https://chat.example.com";
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.cors.allowed_origins = vec![ORIGIN.to_owned()];
+        config
+    }
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn value(res: &ServiceResponse, name: header::HeaderName) -> &str {
+        res.headers().get(name).unwrap().to_str().unwrap()
+    }
+
+    fn preflight(origin: &str) -> test::TestRequest {
+        test::TestRequest::with_uri("/api/ping")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+    }
+
+    #[actix_rt::test]
+    async fn preflight_from_allowed_origin() {
+        let mut app = test::init_service(
+            App::new().app_data(web::Data::new(config())).service(
+                web::scope("/api")
+                    .wrap_fn(handle)
+                    .route("/ping", web::post().to(ok)),
+            ),
+        )
+        .await;
+
+        let res = test::call_service(&mut app, preflight(ORIGIN).to_request()).await;
+        assert_eq!(res.status(), 204);
+        assert_eq!(value(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN), ORIGIN);
+        assert_eq!(
+            value(&res, header::ACCESS_CONTROL_ALLOW_METHODS),
+            ALLOWED_METHODS
+        );
+        assert_eq!(value(&res, header::ACCESS_CONTROL_MAX_AGE), "3600");
+
+        let req = test::TestRequest::post()
+            .uri("/api/ping")
+            .header(header::ORIGIN, ORIGIN)
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(value(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN), ORIGIN);
+        assert_eq!(
+            value(&res, header::ACCESS_CONTROL_EXPOSE_HEADERS),
+            EXPOSED_HEADERS
+        );
+    }
+
+    #[actix_rt::test]
+    async fn no_cors_headers_for_other_origins() {
+        let mut app = test::init_service(
+            App::new().app_data(web::Data::new(config())).service(
+                web::scope("/api")
+                    .wrap_fn(handle)
+                    .route("/ping", web::post().to(ok)),
+            ),
+        )
+        .await;
+
+        let res = test::call_service(&mut app, preflight("https://evil.com").to_request()).await;
+        assert_ne!(res.status(), 204);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let req = test::TestRequest::post()
+            .uri("/api/ping")
+            .header(header::ORIGIN, "https://evil.com")
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 200);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    fn handshake(origin: Option<&str>) -> HttpRequest {
+        let req = test::TestRequest::with_uri("/ws").header(header::HOST, "example.com");
+        match origin {
+            Some(origin) => req.header(header::ORIGIN, origin),
+            None => req,
+        }
+        .to_http_request()
+    }
+
+    #[test]
+    fn websocket_origin() {
+        let config = config();
+        assert!(allows_websocket(&config, &handshake(None)));
+        assert!(allows_websocket(
+            &config,
+            &handshake(Some("https://example.com"))
+        ));
+        assert!(allows_websocket(
+            &config,
+            &handshake(Some("http://EXAMPLE.com"))
+        ));
+        assert!(allows_websocket(&config, &handshake(Some(ORIGIN))));
+        assert!(!allows_websocket(
+            &config,
+            &handshake(Some("https://evil.com"))
+        ));
+        assert!(!allows_websocket(
+            &config,
+            &handshake(Some("https://example.com.evil.com"))
+        ));
+        assert!(!allows_websocket(&config, &handshake(Some("null"))));
+    }
+}
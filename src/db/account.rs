@@ -0,0 +1,190 @@
+#[cfg(feature = "sql")]
+use super::error::{deal_query_result, deal_update_result, Error};
+#[cfg(feature = "sql")]
+use super::moderation::now;
+#[cfg(feature = "sql")]
+use super::user::{self, QueryUser};
+#[cfg(feature = "sql")]
+use super::Conn;
+#[cfg(feature = "sql")]
+use chrono::{Duration, NaiveDateTime};
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sql")]
+use tracing::error;
+
+// 注销后消息的处理方式, 在恢复期结束时执行
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MessagePolicy {
+    // 保留内容, 只匿名化发送者
+    Keep,
+    // 内容替换为占位文字
+    Redact,
+    // 删除消息
+    Erase,
+}
+
+pub const REDACTED: &str = "[deleted]";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountConfig {
+    // 注销后可恢复的天数
+    pub restore_days: i64,
+    pub messages: MessagePolicy,
+}
+
+impl Default for AccountConfig {
+    fn default() -> AccountConfig {
+        AccountConfig {
+            restore_days: 30,
+            messages: MessagePolicy::Keep,
+        }
+    }
+}
+
+impl MessagePolicy {
+    // `keep|redact|erase`, 格式同 `DELETED_MESSAGES`
+    pub fn parse(value: &str) -> Option<MessagePolicy> {
+        match value {
+            "keep" => Some(MessagePolicy::Keep),
+            "redact" => Some(MessagePolicy::Redact),
+            "erase" => Some(MessagePolicy::Erase),
+            _ => None,
+        }
+    }
+}
+
+// 停用账号, 随时可以恢复
+#[cfg(feature = "sql")]
+pub fn deactivate(conn: &Conn, u_id: i32) -> Result<(), Error> {
+    use super::schema::users::dsl::*;
+    let r = diesel::update(users.find(u_id).filter(delete_time.is_null()))
+        .set((
+            delete_time.eq(Some(now())),
+            purge_time.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn);
+    deal_update_result(r)
+}
+
+// 注销账号, `restore_days` 天内可以恢复, 之后由 `purge_expired` 清除
+#[cfg(feature = "sql")]
+pub fn delete(conn: &Conn, u_id: i32, restore_days: i64) -> Result<NaiveDateTime, Error> {
+    use super::schema::users::dsl::*;
+    let deadline = now() + Duration::days(restore_days);
+    let r = diesel::update(users.find(u_id).filter(purged.eq(false)))
+        .set((delete_time.eq(Some(now())), purge_time.eq(Some(deadline))))
+        .execute(conn);
+    deal_update_result(r).map(|_| deadline)
+}
+
+// 已停用或仍在恢复期内的账号, 用户名和密码需匹配
+#[cfg(feature = "sql")]
+pub fn find_restorable(conn: &Conn, u_name: &str, pd: &str) -> Result<QueryUser, Error> {
+    use super::schema::users::dsl::*;
+    let r: QueryResult<QueryUser> = users
+        .select(user::COLUMNS)
+        .filter(user_name.eq(u_name))
+        .filter(passwd.eq(pd))
+        .filter(delete_time.is_not_null())
+        .filter(purged.eq(false))
+        .filter(purge_time.is_null().or(purge_time.gt(now())))
+        .first(conn);
+    deal_query_result(r)
+}
+
+#[cfg(feature = "sql")]
+pub fn restore(conn: &Conn, u_id: i32) -> Result<(), Error> {
+    use super::schema::users::dsl::*;
+    let r = diesel::update(users.find(u_id).filter(purged.eq(false)))
+        .set((
+            delete_time.eq(None::<NaiveDateTime>),
+            purge_time.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn);
+    deal_update_result(r)
+}
+
+// 清除恢复期已过的账号: 匿名化用户资料, 删除关系数据, 按策略处理消息
+#[cfg(feature = "sql")]
+pub fn purge_expired(conn: &Conn, policy: MessagePolicy) -> Result<usize, Error> {
+    use super::schema::users::dsl::*;
+    let r: QueryResult<Vec<i32>> = users
+        .filter(purged.eq(false))
+        .filter(purge_time.le(now()))
+        .select(user_id)
+        .load(conn);
+    let expired = deal_query_result(r)?;
+    for u_id in &expired {
+        if let Err(e) = purge(conn, *u_id, policy) {
+            error!(user_id = u_id, error = ?e, "purge user failed");
+        }
+    }
+    Ok(expired.len())
+}
+
+#[cfg(feature = "sql")]
+fn purge(connection: &Conn, u_id: i32, policy: MessagePolicy) -> Result<(), Error> {
+    use super::schema::{
+        contact_requests, contacts, message_flags, messages, room_members, user_blocks, users,
+    };
+    let r = connection.transaction::<_, diesel::result::Error, _>(|| {
+        match policy {
+            MessagePolicy::Keep => (),
+            MessagePolicy::Redact => {
+                diesel::update(messages::table.filter(messages::from_user.eq(u_id)))
+                    .set(messages::content.eq(REDACTED))
+                    .execute(connection)?;
+            }
+            MessagePolicy::Erase => {
+                let own = messages::table
+                    .filter(messages::from_user.eq(u_id))
+                    .select(messages::message_id);
+                diesel::delete(message_flags::table.filter(message_flags::message_id.eq_any(own)))
+                    .execute(connection)?;
+                diesel::delete(messages::table.filter(messages::from_user.eq(u_id)))
+                    .execute(connection)?;
+            }
+        }
+        diesel::delete(room_members::table.filter(room_members::user_id.eq(u_id)))
+            .execute(connection)?;
+        diesel::delete(
+            contacts::table.filter(contacts::user_id.eq(u_id).or(contacts::contact_id.eq(u_id))),
+        )
+        .execute(connection)?;
+        diesel::delete(
+            contact_requests::table.filter(
+                contact_requests::from_user
+                    .eq(u_id)
+                    .or(contact_requests::to_user.eq(u_id)),
+            ),
+        )
+        .execute(connection)?;
+        diesel::delete(
+            user_blocks::table.filter(
+                user_blocks::blocker
+                    .eq(u_id)
+                    .or(user_blocks::blocked.eq(u_id)),
+            ),
+        )
+        .execute(connection)?;
+        // 用户名空出来可以重新注册; 密码清空, 且 delete_time 保留, 无法再登录
+        diesel::update(users::table.find(u_id))
+            .set((
+                users::user_name.eq(format!("deleted_{}", u_id)),
+                users::passwd.eq(""),
+                users::display_name.eq(None::<String>),
+                users::avatar.eq(None::<String>),
+                users::email.eq(None::<String>),
+                users::phone.eq(None::<String>),
+                users::bio.eq(None::<String>),
+                users::purged.eq(true),
+            ))
+            .execute(connection)?;
+        Ok(())
+    });
+    deal_query_result(r)
+}
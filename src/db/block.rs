@@ -0,0 +1,63 @@
+#[cfg(feature = "sql")]
+use super::error::{deal_insert_result, deal_query_result, deal_update_result, Error};
+#[cfg(feature = "sql")]
+use super::schema::user_blocks;
+#[cfg(feature = "sql")]
+use super::Conn;
+use chrono::NaiveDateTime;
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+use serde::Serialize;
+
+#[derive(Queryable, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryBlock {
+    pub blocker: i32,
+    pub blocked: i32,
+    pub create_time: NaiveDateTime,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "user_blocks"]
+struct InsertableBlock {
+    blocker: i32,
+    blocked: i32,
+}
+
+#[cfg(feature = "sql")]
+pub fn add(conn: &Conn, u_id: i32, other: i32) -> Result<(), Error> {
+    let new_block = InsertableBlock {
+        blocker: u_id,
+        blocked: other,
+    };
+    let r = diesel::insert_into(user_blocks::table)
+        .values(&new_block)
+        .execute(conn);
+    deal_insert_result(r)
+}
+
+#[cfg(feature = "sql")]
+pub fn remove(conn: &Conn, u_id: i32, other: i32) -> Result<(), Error> {
+    use super::schema::user_blocks::dsl::*;
+    let r = diesel::delete(user_blocks.find((u_id, other))).execute(conn);
+    deal_update_result(r)
+}
+
+#[cfg(feature = "sql")]
+pub fn list(conn: &Conn, u_id: i32) -> Result<Vec<QueryBlock>, Error> {
+    use super::schema::user_blocks::dsl::*;
+    let r: QueryResult<Vec<QueryBlock>> = user_blocks
+        .filter(blocker.eq(u_id))
+        .order(create_time.desc())
+        .load(conn);
+    deal_query_result(r)
+}
+
+// u_id 是否拉黑了 other
+#[cfg(feature = "sql")]
+pub fn is_blocked(conn: &Conn, u_id: i32, other: i32) -> Result<bool, Error> {
+    use super::schema::user_blocks::dsl::*;
+    let r: QueryResult<i64> = user_blocks.find((u_id, other)).count().get_result(conn);
+    deal_query_result(r).map(|c| c > 0)
+}
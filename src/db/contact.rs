@@ -0,0 +1,193 @@
+#[cfg(feature = "sql")]
+use super::error::{deal_insert_result, deal_query_result, deal_update_result, Error};
+#[cfg(feature = "sql")]
+use super::schema::{contact_requests, contacts};
+#[cfg(feature = "sql")]
+use super::Conn;
+use super::Tiny;
+use chrono::NaiveDateTime;
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+use serde::Serialize;
+
+pub const PENDING: Tiny = 0;
+pub const ACCEPTED: Tiny = 1;
+pub const DECLINED: Tiny = 2;
+
+#[derive(Queryable, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryContactRequest {
+    pub request_id: i64,
+    pub from_user: i32,
+    pub to_user: i32,
+    pub state: Tiny,
+    pub message: Option<String>,
+    pub create_time: NaiveDateTime,
+    pub updata_time: Option<NaiveDateTime>,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "contact_requests"]
+struct InsertableRequest<'a> {
+    from_user: i32,
+    to_user: i32,
+    message: Option<&'a str>,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "contacts"]
+struct InsertableContact {
+    user_id: i32,
+    contact_id: i32,
+}
+
+#[cfg(feature = "sql")]
+fn find_request_between(conn: &Conn, from: i32, to: i32) -> Result<QueryContactRequest, Error> {
+    use super::schema::contact_requests::dsl::*;
+    let r: QueryResult<QueryContactRequest> = contact_requests
+        .filter(from_user.eq(from))
+        .filter(to_user.eq(to))
+        .first(conn);
+    deal_query_result(r)
+}
+
+// 发起好友申请, 之前的申请会被重置为待处理
+#[cfg(feature = "sql")]
+pub fn request(
+    conn: &Conn,
+    from: i32,
+    to: i32,
+    text: Option<&str>,
+) -> Result<QueryContactRequest, Error> {
+    use super::schema::contact_requests::dsl::*;
+    match find_request_between(conn, from, to) {
+        Ok(old) => {
+            let r = diesel::update(contact_requests.find(old.request_id))
+                .set((state.eq(PENDING), message.eq(text)))
+                .execute(conn);
+            deal_query_result(r)?;
+        }
+        Err(Error::NotFound) => {
+            let new_request = InsertableRequest {
+                from_user: from,
+                to_user: to,
+                message: text,
+            };
+            let r = diesel::insert_into(contact_requests)
+                .values(&new_request)
+                .execute(conn);
+            deal_insert_result(r)?;
+        }
+        Err(e) => return Err(e),
+    }
+    find_request_between(conn, from, to)
+}
+
+#[cfg(feature = "sql")]
+pub fn find_request(conn: &Conn, r_id: i64) -> Result<QueryContactRequest, Error> {
+    use super::schema::contact_requests::dsl::*;
+    let r: QueryResult<QueryContactRequest> = contact_requests.find(r_id).first(conn);
+    deal_query_result(r)
+}
+
+// 收到的待处理申请
+#[cfg(feature = "sql")]
+pub fn pending_for(conn: &Conn, u_id: i32) -> Result<Vec<QueryContactRequest>, Error> {
+    use super::schema::contact_requests::dsl::*;
+    let r: QueryResult<Vec<QueryContactRequest>> = contact_requests
+        .filter(to_user.eq(u_id))
+        .filter(state.eq(PENDING))
+        .order(create_time.desc())
+        .load(conn);
+    deal_query_result(r)
+}
+
+// 同意时双方互加好友, 与更新申请状态在同一事务中
+#[cfg(feature = "sql")]
+pub fn answer(conn: &Conn, r_id: i64, new_state: Tiny) -> Result<(), Error> {
+    use super::schema::contact_requests::dsl::*;
+    conn.transaction(|| {
+        let req = find_request(conn, r_id)?;
+        let r = diesel::update(contact_requests.find(r_id).filter(state.eq(PENDING)))
+            .set(state.eq(new_state))
+            .execute(conn);
+        deal_update_result(r)?;
+        if new_state == ACCEPTED {
+            add(conn, req.from_user, req.to_user)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(feature = "sql")]
+fn add(conn: &Conn, a: i32, b: i32) -> Result<(), Error> {
+    let rows = vec![
+        InsertableContact {
+            user_id: a,
+            contact_id: b,
+        },
+        InsertableContact {
+            user_id: b,
+            contact_id: a,
+        },
+    ];
+    #[cfg(feature = "mysql")]
+    let r = diesel::insert_or_ignore_into(contacts::table)
+        .values(&rows)
+        .execute(conn);
+    #[cfg(feature = "postgres")]
+    let r = diesel::insert_into(contacts::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(conn);
+    deal_query_result(r).map(|_| ())
+}
+
+#[cfg(feature = "sql")]
+pub fn remove(conn: &Conn, a: i32, b: i32) -> Result<(), Error> {
+    use super::schema::contacts::dsl::*;
+    let r = diesel::delete(
+        contacts.filter(
+            user_id
+                .eq(a)
+                .and(contact_id.eq(b))
+                .or(user_id.eq(b).and(contact_id.eq(a))),
+        ),
+    )
+    .execute(conn);
+    match deal_query_result(r)? {
+        0 => Err(Error::NotFound),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "sql")]
+pub fn list(conn: &Conn, u_id: i32) -> Result<Vec<i32>, Error> {
+    use super::schema::contacts::dsl::*;
+    let r: QueryResult<Vec<i32>> = contacts
+        .filter(user_id.eq(u_id))
+        .select(contact_id)
+        .load(conn);
+    deal_query_result(r)
+}
+
+#[cfg(feature = "sql")]
+pub fn are_contacts(conn: &Conn, a: i32, b: i32) -> Result<bool, Error> {
+    use super::schema::contacts::dsl::*;
+    let r: QueryResult<i64> = contacts.find((a, b)).count().get_result(conn);
+    deal_query_result(r).map(|c| c > 0)
+}
+
+// 对方设置了只允许好友发起单聊时, 非好友只能回复对方发起的会话
+#[cfg(feature = "sql")]
+pub fn may_message(conn: &Conn, from: i32, to: i32) -> Result<bool, Error> {
+    if !super::user::find_with_id(conn, to)?.contacts_only {
+        return Ok(true);
+    }
+    if are_contacts(conn, to, from)? {
+        return Ok(true);
+    }
+    super::message::has_p2p(conn, to, from)
+}
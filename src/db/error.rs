@@ -1,63 +1,114 @@
-use super::schema::users;
-use diesel::deserialize::Queryable;
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use diesel::result::DatabaseErrorKind;
+#[cfg(feature = "sql")]
 use diesel::QueryResult;
+use serde_json::json;
+use std::fmt;
+use tracing::error;
 
 #[derive(Debug)]
 pub enum Error {
+    // 只由 SQL 查询产生
+    #[cfg_attr(not(feature = "sql"), allow(dead_code))]
     InsertNumError,
     DuplicateData(String),
     WapperError(String),
     NotFound,
     ForeignKeyViolation(String),
+    // 不连接数据库时的 SQL 查询, 如迁移状态和管理命令
+    Unsupported,
 }
 
-pub fn deal_insert_result(r: QueryResult<usize>) -> Result<(), Error> {
-    match r {
-        Ok(s) => {
-            if s == 1 {
-                Ok(())
-            } else {
-                Err(Error::InsertNumError)
-            }
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InsertNumError => write!(f, "unexpected number of inserted rows"),
+            Error::DuplicateData(detail) => write!(f, "duplicate data: {}", detail),
+            Error::WapperError(detail) => write!(f, "database error: {}", detail),
+            Error::NotFound => write!(f, "not found"),
+            Error::ForeignKeyViolation(detail) => write!(f, "foreign key violation: {}", detail),
+            Error::Unsupported => write!(f, "not supported by the storage backend"),
         }
-        Err(e) => {
-            if let diesel::result::Error::DatabaseError(UniqueViolation, _) = e {
-                Err(Error::DuplicateData(e.to_string()))
-            } else {
-                Err(Error::WapperError(e.to_string()))
+    }
+}
+
+impl std::error::Error for Error {}
+
+// 按错误类型区分, 不再把所有数据库错误都当作 WapperError
+impl From<diesel::result::Error> for Error {
+    fn from(e: diesel::result::Error) -> Error {
+        match e {
+            diesel::NotFound => Error::NotFound,
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, ref info) => {
+                Error::DuplicateData(info.constraint_name().unwrap_or(info.message()).to_owned())
             }
+            diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::ForeignKeyViolation,
+                ref info,
+            ) => Error::ForeignKeyViolation(
+                info.constraint_name().unwrap_or(info.message()).to_owned(),
+            ),
+            e => Error::WapperError(e.to_string()),
         }
     }
 }
 
-pub fn deal_query_result<T>(r: QueryResult<T>) -> Result<T, Error> {
-    match r {
-        Ok(u) => Ok(u),
-        Err(e) => {
-            if let diesel::NotFound = e {
-                Err(Error::NotFound)
-            } else {
-                Err(Error::WapperError(e.to_string()))
-            }
+impl From<BlockingError<Error>> for Error {
+    fn from(e: BlockingError<Error>) -> Error {
+        match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => Error::WapperError("blocking call canceled".to_owned()),
         }
     }
 }
 
-pub fn deal_update_result(r: QueryResult<usize>) -> Result<(), Error> {
-    match r {
-        Ok(s) => {
-            if s == 1 {
-                Ok(())
-            } else {
-                Err(Error::NotFound)
-            }
+// 与 `api::models::fail` 的格式相同(state 1 为失败), 内部错误不向客户端暴露细节
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::DuplicateData(_) | Error::ForeignKeyViolation(_) => StatusCode::CONFLICT,
+            Error::Unsupported => StatusCode::NOT_IMPLEMENTED,
+            Error::InsertNumError | Error::WapperError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
-        Err(e) => {
-            if let diesel::NotFound = e {
-                Err(Error::NotFound)
-            } else {
-                Err(Error::WapperError(e.to_string()))
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            Error::NotFound => "not found".to_owned(),
+            Error::DuplicateData(_) => "already exists".to_owned(),
+            Error::ForeignKeyViolation(_) => "referenced data does not exist".to_owned(),
+            Error::Unsupported => self.to_string(),
+            Error::InsertNumError | Error::WapperError(_) => {
+                error!(error = %self, "internal error");
+                "internal error".to_owned()
             }
-        }
+        };
+        HttpResponse::build(self.status_code()).json(json!({ "message": message, "state": 1 }))
+    }
+}
+
+#[cfg(feature = "sql")]
+pub fn deal_insert_result(r: QueryResult<usize>) -> Result<(), Error> {
+    match r {
+        Ok(1) => Ok(()),
+        Ok(_) => Err(Error::InsertNumError),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(feature = "sql")]
+pub fn deal_query_result<T>(r: QueryResult<T>) -> Result<T, Error> {
+    r.map_err(Error::from)
+}
+
+#[cfg(feature = "sql")]
+pub fn deal_update_result(r: QueryResult<usize>) -> Result<(), Error> {
+    match r {
+        Ok(1) => Ok(()),
+        Ok(_) => Err(Error::NotFound),
+        Err(e) => Err(e.into()),
     }
 }
@@ -0,0 +1,845 @@
+use super::account::{MessagePolicy, REDACTED};
+use super::block::QueryBlock;
+use super::contact::{QueryContactRequest, ACCEPTED, PENDING};
+use super::error::Error;
+use super::message::{QueryMessage, BROADCAST_MESSAGE, P2P_MESSAGE, ROOM_MESSAGE};
+use super::moderation::{now, QueryAuditLog, SanctionKind};
+use super::room::RoomRole;
+use super::search::{SearchEngine, SearchQuery};
+use super::storage::Storage;
+use super::user::{ProfileChangeset, QueryProfile, QueryUser, Role, UserSummary};
+use super::Tiny;
+use chrono::{Duration, NaiveDateTime};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+
+// 与 users 表的 AUTO_INCREMENT 起始值一致
+const FIRST_USER_ID: i32 = 100000;
+
+#[derive(Default)]
+struct State {
+    users: BTreeMap<i32, QueryUser>,
+    // 停用或注销的账号
+    deleted: BTreeMap<i32, Deletion>,
+    // (房间, 用户) -> 角色
+    members: BTreeMap<(String, i32), RoomRole>,
+    messages: Vec<QueryMessage>,
+    // (消息 id, 过滤器, 原因)
+    flags: Vec<(i64, String, String)>,
+    sanctions: Vec<Sanction>,
+    audit_logs: Vec<QueryAuditLog>,
+    // 按拉黑时间先后
+    blocks: Vec<QueryBlock>,
+    contact_requests: Vec<QueryContactRequest>,
+    // 双向各存一份, 与 contacts 表一致
+    contacts: BTreeSet<(i32, i32)>,
+}
+
+struct Sanction {
+    room: Option<String>,
+    user_id: i32,
+    kind: SanctionKind,
+    expire: Option<NaiveDateTime>,
+}
+
+struct Deletion {
+    time: NaiveDateTime,
+    // 停用的账号为 None, 不会被清除
+    purge_time: Option<NaiveDateTime>,
+    purged: bool,
+}
+
+impl Sanction {
+    fn is(&self, room: Option<&str>, user_id: i32, kind: SanctionKind) -> bool {
+        self.user_id == user_id && self.kind == kind && self.room.as_deref() == room
+    }
+}
+
+impl State {
+    // 与 SQL 后端一致, 停用/注销的账号视为不存在
+    fn user(&self, user_id: i32) -> Result<&QueryUser, Error> {
+        if self.deleted.contains_key(&user_id) {
+            return Err(Error::NotFound);
+        }
+        self.users.get(&user_id).ok_or(Error::NotFound)
+    }
+
+    fn user_mut(&mut self, user_id: i32) -> Result<&mut QueryUser, Error> {
+        if self.deleted.contains_key(&user_id) {
+            return Err(Error::NotFound);
+        }
+        self.users.get_mut(&user_id).ok_or(Error::NotFound)
+    }
+
+    fn find_by(&self, pred: impl Fn(&QueryUser) -> bool) -> Result<QueryUser, Error> {
+        self.users
+            .values()
+            .filter(|u| !self.deleted.contains_key(&u.user_id))
+            .find(|u| pred(u))
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    // 匿名化用户资料, 删除关系数据, 按策略处理消息
+    fn purge(&mut self, user_id: i32, policy: MessagePolicy) {
+        match policy {
+            MessagePolicy::Keep => (),
+            MessagePolicy::Redact => {
+                for m in self.messages.iter_mut().filter(|m| m.from_user == user_id) {
+                    m.content = REDACTED.to_owned();
+                }
+            }
+            MessagePolicy::Erase => {
+                let own: BTreeSet<i64> = self
+                    .messages
+                    .iter()
+                    .filter(|m| m.from_user == user_id)
+                    .map(|m| m.message_id)
+                    .collect();
+                self.flags.retain(|(id, _, _)| !own.contains(id));
+                self.messages.retain(|m| m.from_user != user_id);
+            }
+        }
+        self.members.retain(|(_, u), _| *u != user_id);
+        self.contacts.retain(|&(a, b)| a != user_id && b != user_id);
+        self.contact_requests
+            .retain(|r| r.from_user != user_id && r.to_user != user_id);
+        self.blocks
+            .retain(|b| b.blocker != user_id && b.blocked != user_id);
+        if let Some(u) = self.users.get_mut(&user_id) {
+            u.user_name = format!("deleted_{}", user_id);
+            u.passwd = String::new();
+            u.display_name = None;
+            u.avatar = None;
+            u.email = None;
+            u.phone = None;
+            u.bio = None;
+        }
+        if let Some(d) = self.deleted.get_mut(&user_id) {
+            d.purged = true;
+        }
+    }
+
+    fn taken(&self, user_id: i32, pred: impl Fn(&QueryUser) -> bool) -> bool {
+        self.users.values().any(|u| u.user_id != user_id && pred(u))
+    }
+
+    fn add_message(
+        &mut self,
+        from: i32,
+        message_type: Tiny,
+        room_name: Option<&str>,
+        to_user: Option<i32>,
+        text: &str,
+    ) -> QueryMessage {
+        let m = QueryMessage {
+            message_id: self.messages.len() as i64 + 1,
+            from_user: from,
+            message_type,
+            room_name: room_name.map(|r| r.to_owned()),
+            to_user,
+            content: text.to_owned(),
+            create_time: now(),
+        };
+        self.messages.push(m.clone());
+        m
+    }
+
+    fn readable(&self, reader: i32, m: &QueryMessage) -> bool {
+        match m.message_type {
+            ROOM_MESSAGE => m
+                .room_name
+                .as_ref()
+                .is_some_and(|r| self.members.contains_key(&(r.to_owned(), reader))),
+            P2P_MESSAGE => m.from_user == reader || m.to_user == Some(reader),
+            _ => m.message_type == BROADCAST_MESSAGE,
+        }
+    }
+}
+
+// 数据全部保存在进程内存中, 用于无数据库时运行服务和测试, 重启后不保留
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    fn state(&self) -> Result<MutexGuard<'_, State>, Error> {
+        self.state
+            .lock()
+            .map_err(|e| Error::WapperError(e.to_string()))
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn add_user(&self, name: String, passwd: String) -> Result<(), Error> {
+        let mut state = self.state()?;
+        if state.users.values().any(|u| u.user_name == name) {
+            return Err(Error::DuplicateData("user_name".to_owned()));
+        }
+        let user_id = state
+            .users
+            .keys()
+            .next_back()
+            .map_or(FIRST_USER_ID, |id| id + 1);
+        state.users.insert(
+            user_id,
+            QueryUser {
+                user_id,
+                user_name: name,
+                passwd,
+                create_time: now(),
+                role: 0,
+                last_broadcast_id: 0,
+                contacts_only: false,
+                display_name: None,
+                avatar: None,
+                email: None,
+                phone: None,
+                bio: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn verify_user(&self, name: &str, passwd: &str) -> Result<QueryUser, Error> {
+        self.state()?
+            .find_by(|u| u.user_name == name && u.passwd == passwd)
+    }
+
+    fn find_user(&self, user_id: i32) -> Result<QueryUser, Error> {
+        self.state()?.user(user_id).cloned()
+    }
+
+    fn find_user_by_name(&self, name: &str) -> Result<QueryUser, Error> {
+        self.state()?.find_by(|u| u.user_name == name)
+    }
+
+    fn find_user_by_email(&self, email: &str) -> Result<QueryUser, Error> {
+        self.state()?.find_by(|u| u.email.as_deref() == Some(email))
+    }
+
+    fn user_names(&self, user_ids: &[i32]) -> Result<Vec<(i32, String)>, Error> {
+        let state = self.state()?;
+        Ok(user_ids
+            .iter()
+            .filter_map(|id| state.users.get(id))
+            .map(|u| (u.user_id, u.user_name.clone()))
+            .collect())
+    }
+
+    fn change_passwd(&self, user_id: i32, old: &str, new: &str) -> Result<(), Error> {
+        let mut state = self.state()?;
+        let u = state.user_mut(user_id)?;
+        if u.passwd != old {
+            return Err(Error::NotFound);
+        }
+        u.passwd = new.to_owned();
+        Ok(())
+    }
+
+    fn set_passwd(&self, user_id: i32, new: &str) -> Result<(), Error> {
+        self.state()?.user_mut(user_id)?.passwd = new.to_owned();
+        Ok(())
+    }
+
+    fn set_contacts_only(&self, user_id: i32, only: bool) -> Result<(), Error> {
+        self.state()?.user_mut(user_id)?.contacts_only = only;
+        Ok(())
+    }
+
+    fn profile(&self, user_id: i32) -> Result<QueryProfile, Error> {
+        let state = self.state()?;
+        let u = state.user(user_id)?;
+        Ok(QueryProfile {
+            user_id: u.user_id,
+            user_name: u.user_name.clone(),
+            display_name: u.display_name.clone(),
+            avatar: u.avatar.clone(),
+            email: u.email.clone(),
+            phone: u.phone.clone(),
+            bio: u.bio.clone(),
+        })
+    }
+
+    fn update_profile(&self, user_id: i32, changes: &ProfileChangeset) -> Result<(), Error> {
+        let mut state = self.state()?;
+        if let Some(Some(ref v)) = changes.email {
+            if state.taken(user_id, |u| u.email.as_ref() == Some(v)) {
+                return Err(Error::DuplicateData("email".to_owned()));
+            }
+        }
+        if let Some(Some(ref v)) = changes.phone {
+            if state.taken(user_id, |u| u.phone.as_ref() == Some(v)) {
+                return Err(Error::DuplicateData("phone".to_owned()));
+            }
+        }
+        let u = state.user_mut(user_id)?;
+        let fields = [
+            (&mut u.display_name, &changes.display_name),
+            (&mut u.avatar, &changes.avatar),
+            (&mut u.email, &changes.email),
+            (&mut u.phone, &changes.phone),
+            (&mut u.bio, &changes.bio),
+        ];
+        for (field, change) in fields {
+            if let Some(v) = change {
+                *field = v.clone();
+            }
+        }
+        Ok(())
+    }
+
+    fn set_role(&self, user_id: i32, role: Role) -> Result<(), Error> {
+        self.state()?.user_mut(user_id)?.role = role as Tiny;
+        Ok(())
+    }
+
+    fn users(&self, limit: i64, offset: i64) -> Result<Vec<UserSummary>, Error> {
+        let state = self.state()?;
+        Ok(state
+            .users
+            .values()
+            .filter(|u| state.deleted.get(&u.user_id).is_none_or(|d| !d.purged))
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .map(|u| UserSummary {
+                user_id: u.user_id,
+                user_name: u.user_name.clone(),
+                role: u.role(),
+                create_time: u.create_time,
+                delete_time: state.deleted.get(&u.user_id).map(|d| d.time),
+            })
+            .collect())
+    }
+
+    fn deactivate(&self, user_id: i32) -> Result<(), Error> {
+        let mut state = self.state()?;
+        state.user(user_id)?;
+        state.deleted.insert(
+            user_id,
+            Deletion {
+                time: now(),
+                purge_time: None,
+                purged: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn delete_account(&self, user_id: i32, restore_days: i64) -> Result<NaiveDateTime, Error> {
+        let mut state = self.state()?;
+        if !state.users.contains_key(&user_id)
+            || state.deleted.get(&user_id).is_some_and(|d| d.purged)
+        {
+            return Err(Error::NotFound);
+        }
+        let deadline = now() + Duration::days(restore_days);
+        state.deleted.insert(
+            user_id,
+            Deletion {
+                time: now(),
+                purge_time: Some(deadline),
+                purged: false,
+            },
+        );
+        Ok(deadline)
+    }
+
+    fn find_restorable(&self, name: &str, passwd: &str) -> Result<QueryUser, Error> {
+        let state = self.state()?;
+        let now = now();
+        state
+            .users
+            .values()
+            .filter(|u| u.user_name == name && u.passwd == passwd)
+            .find(|u| {
+                state
+                    .deleted
+                    .get(&u.user_id)
+                    .is_some_and(|d| !d.purged && d.purge_time.is_none_or(|t| t > now))
+            })
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn restore(&self, user_id: i32) -> Result<(), Error> {
+        let mut state = self.state()?;
+        if !state.users.contains_key(&user_id)
+            || state.deleted.get(&user_id).is_some_and(|d| d.purged)
+        {
+            return Err(Error::NotFound);
+        }
+        state.deleted.remove(&user_id);
+        Ok(())
+    }
+
+    fn purge_expired(&self, policy: MessagePolicy) -> Result<usize, Error> {
+        let mut state = self.state()?;
+        let now = now();
+        let expired: Vec<i32> = state
+            .deleted
+            .iter()
+            .filter(|(_, d)| !d.purged && d.purge_time.is_some_and(|t| t <= now))
+            .map(|(id, _)| *id)
+            .collect();
+        for user_id in &expired {
+            state.purge(*user_id, policy);
+        }
+        Ok(expired.len())
+    }
+
+    fn join_room(&self, user_id: i32, room: &str) -> Result<(), Error> {
+        self.state()?
+            .members
+            .entry((room.to_owned(), user_id))
+            .or_insert(RoomRole::Member);
+        Ok(())
+    }
+
+    fn leave_room(&self, user_id: i32, room: &str) -> Result<(), Error> {
+        self.state()?.members.remove(&(room.to_owned(), user_id));
+        Ok(())
+    }
+
+    fn room_role(&self, user_id: i32, room: &str) -> Result<Option<RoomRole>, Error> {
+        Ok(self
+            .state()?
+            .members
+            .get(&(room.to_owned(), user_id))
+            .cloned())
+    }
+
+    fn room_members(&self) -> Result<Vec<(String, i64)>, Error> {
+        let mut rooms: Vec<(String, i64)> = Vec::new();
+        // members 按 (房间, 用户) 排序, 同一房间的成员相邻
+        for (room, _) in self.state()?.members.keys() {
+            match rooms.last_mut() {
+                Some((name, count)) if name == room => *count += 1,
+                _ => rooms.push((room.clone(), 1)),
+            }
+        }
+        Ok(rooms)
+    }
+
+    fn set_room_role(&self, user_id: i32, room: &str, role: RoomRole) -> Result<(), Error> {
+        match self.state()?.members.get_mut(&(room.to_owned(), user_id)) {
+            Some(r) => {
+                *r = role;
+                Ok(())
+            }
+            None => Err(Error::NotFound),
+        }
+    }
+
+    fn add_block(&self, user_id: i32, other: i32) -> Result<(), Error> {
+        let mut state = self.state()?;
+        if state
+            .blocks
+            .iter()
+            .any(|b| b.blocker == user_id && b.blocked == other)
+        {
+            return Err(Error::DuplicateData("user_blocks".to_owned()));
+        }
+        state.blocks.push(QueryBlock {
+            blocker: user_id,
+            blocked: other,
+            create_time: now(),
+        });
+        Ok(())
+    }
+
+    fn remove_block(&self, user_id: i32, other: i32) -> Result<(), Error> {
+        let mut state = self.state()?;
+        let len = state.blocks.len();
+        state
+            .blocks
+            .retain(|b| !(b.blocker == user_id && b.blocked == other));
+        if state.blocks.len() == len {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    fn blocks(&self, user_id: i32) -> Result<Vec<QueryBlock>, Error> {
+        Ok(self
+            .state()?
+            .blocks
+            .iter()
+            .rev()
+            .filter(|b| b.blocker == user_id)
+            .cloned()
+            .collect())
+    }
+
+    fn is_blocked(&self, user_id: i32, other: i32) -> Result<bool, Error> {
+        Ok(self
+            .state()?
+            .blocks
+            .iter()
+            .any(|b| b.blocker == user_id && b.blocked == other))
+    }
+
+    fn contact_request(
+        &self,
+        from: i32,
+        to: i32,
+        message: Option<&str>,
+    ) -> Result<QueryContactRequest, Error> {
+        let mut state = self.state()?;
+        let message = message.map(|m| m.to_owned());
+        let old = state
+            .contact_requests
+            .iter_mut()
+            .find(|r| r.from_user == from && r.to_user == to);
+        if let Some(req) = old {
+            req.state = PENDING;
+            req.message = message;
+            req.updata_time = Some(now());
+            return Ok(req.clone());
+        }
+        let req = QueryContactRequest {
+            request_id: state.contact_requests.len() as i64 + 1,
+            from_user: from,
+            to_user: to,
+            state: PENDING,
+            message,
+            create_time: now(),
+            updata_time: None,
+        };
+        state.contact_requests.push(req.clone());
+        Ok(req)
+    }
+
+    fn find_contact_request(&self, request_id: i64) -> Result<QueryContactRequest, Error> {
+        self.state()?
+            .contact_requests
+            .iter()
+            .find(|r| r.request_id == request_id)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn pending_contact_requests(&self, user_id: i32) -> Result<Vec<QueryContactRequest>, Error> {
+        Ok(self
+            .state()?
+            .contact_requests
+            .iter()
+            .rev()
+            .filter(|r| r.to_user == user_id && r.state == PENDING)
+            .cloned()
+            .collect())
+    }
+
+    fn answer_contact_request(&self, request_id: i64, state: Tiny) -> Result<(), Error> {
+        let mut guard = self.state()?;
+        let req = guard
+            .contact_requests
+            .iter_mut()
+            .find(|r| r.request_id == request_id && r.state == PENDING)
+            .ok_or(Error::NotFound)?;
+        req.state = state;
+        req.updata_time = Some(now());
+        let (a, b) = (req.from_user, req.to_user);
+        if state == ACCEPTED {
+            guard.contacts.insert((a, b));
+            guard.contacts.insert((b, a));
+        }
+        Ok(())
+    }
+
+    fn remove_contact(&self, user_id: i32, other: i32) -> Result<(), Error> {
+        let mut state = self.state()?;
+        let removed = state.contacts.remove(&(user_id, other));
+        let removed = state.contacts.remove(&(other, user_id)) || removed;
+        if !removed {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    fn contacts(&self, user_id: i32) -> Result<Vec<i32>, Error> {
+        Ok(self
+            .state()?
+            .contacts
+            .range((user_id, i32::MIN)..=(user_id, i32::MAX))
+            .map(|&(_, c)| c)
+            .collect())
+    }
+
+    fn are_contacts(&self, user_id: i32, other: i32) -> Result<bool, Error> {
+        Ok(self.state()?.contacts.contains(&(user_id, other)))
+    }
+
+    fn may_message(&self, from: i32, to: i32) -> Result<bool, Error> {
+        let state = self.state()?;
+        if !state.user(to)?.contacts_only || state.contacts.contains(&(to, from)) {
+            return Ok(true);
+        }
+        Ok(state
+            .messages
+            .iter()
+            .any(|m| m.message_type == P2P_MESSAGE && m.from_user == to && m.to_user == Some(from)))
+    }
+
+    fn add_sanction(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+        expire: Option<NaiveDateTime>,
+    ) -> Result<(), Error> {
+        let mut state = self.state()?;
+        state.sanctions.retain(|s| !s.is(room, user_id, kind));
+        state.sanctions.push(Sanction {
+            room: room.map(|r| r.to_owned()),
+            user_id,
+            kind,
+            expire,
+        });
+        Ok(())
+    }
+
+    fn remove_sanction(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+    ) -> Result<(), Error> {
+        self.state()?
+            .sanctions
+            .retain(|s| !s.is(room, user_id, kind));
+        Ok(())
+    }
+
+    fn is_sanctioned(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+    ) -> Result<bool, Error> {
+        let now = now();
+        Ok(self.state()?.sanctions.iter().any(|s| {
+            s.user_id == user_id
+                && s.kind == kind
+                && s.expire.is_none_or(|t| t > now)
+                && (s.room.is_none() || s.room.as_deref() == room)
+        }))
+    }
+
+    fn audit(
+        &self,
+        operator: i32,
+        action: &str,
+        target: i32,
+        room: Option<&str>,
+        detail: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut state = self.state()?;
+        let log_id = state.audit_logs.len() as i64 + 1;
+        state.audit_logs.push(QueryAuditLog {
+            log_id,
+            operator,
+            action: action.to_owned(),
+            target_user: target,
+            room_name: room.map(|r| r.to_owned()),
+            detail: detail.map(|d| d.to_owned()),
+            create_time: now(),
+        });
+        Ok(())
+    }
+
+    fn audit_logs(&self, limit: i64, offset: i64) -> Result<Vec<QueryAuditLog>, Error> {
+        Ok(self
+            .state()?
+            .audit_logs
+            .iter()
+            .rev()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn add_room_message(&self, from: i32, room: &str, text: &str) -> Result<QueryMessage, Error> {
+        Ok(self
+            .state()?
+            .add_message(from, ROOM_MESSAGE, Some(room), None, text))
+    }
+
+    fn add_p2p_message(&self, from: i32, to: i32, text: &str) -> Result<QueryMessage, Error> {
+        Ok(self
+            .state()?
+            .add_message(from, P2P_MESSAGE, None, Some(to), text))
+    }
+
+    fn add_broadcast_message(&self, from: i32, text: &str) -> Result<QueryMessage, Error> {
+        Ok(self
+            .state()?
+            .add_message(from, BROADCAST_MESSAGE, None, None, text))
+    }
+
+    fn flag_message(&self, message_id: i64, filter: &str, reason: &str) -> Result<(), Error> {
+        self.state()?
+            .flags
+            .push((message_id, filter.to_owned(), reason.to_owned()));
+        Ok(())
+    }
+
+    fn pending_broadcasts(&self, user_id: i32, max: i64) -> Result<Vec<QueryMessage>, Error> {
+        let state = self.state()?;
+        let u = state.user(user_id)?;
+        let mut pending: Vec<QueryMessage> = state
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| m.message_type == BROADCAST_MESSAGE)
+            .filter(|m| m.message_id > u.last_broadcast_id && m.create_time >= u.create_time)
+            .take(max.max(0) as usize)
+            .cloned()
+            .collect();
+        pending.reverse();
+        Ok(pending)
+    }
+
+    fn mark_broadcast_read(&self, user_ids: &[i32], message_id: i64) -> Result<(), Error> {
+        let mut state = self.state()?;
+        for id in user_ids {
+            if let Some(u) = state.users.get_mut(id) {
+                u.last_broadcast_id = u.last_broadcast_id.max(message_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+// 子串匹配, 任一关键词命中即返回, 与 MySQL 布尔模式不带运算符时一致
+impl SearchEngine for MemoryStorage {
+    fn index(&self, _: &QueryMessage) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn search(&self, reader: i32, query: &SearchQuery) -> Result<Vec<QueryMessage>, Error> {
+        let state = self.state()?;
+        let terms: Vec<String> = query.terms.iter().map(|t| t.to_lowercase()).collect();
+        Ok(state
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| state.readable(reader, m))
+            .filter(|m| {
+                let content = m.content.to_lowercase();
+                terms.iter().any(|t| content.contains(t.as_str()))
+            })
+            .filter(|m| {
+                query.room.as_ref().is_none_or(|r| {
+                    m.message_type == ROOM_MESSAGE && m.room_name.as_ref() == Some(r)
+                })
+            })
+            .filter(|m| {
+                query.peer.is_none_or(|peer| {
+                    m.message_type == P2P_MESSAGE
+                        && ((m.from_user == reader && m.to_user == Some(peer))
+                            || (m.from_user == peer && m.to_user == Some(reader)))
+                })
+            })
+            .filter(|m| query.sender.is_none_or(|s| m.from_user == s))
+            .filter(|m| query.since.is_none_or(|t| m.create_time >= t))
+            .filter(|m| query.until.is_none_or(|t| m.create_time <= t))
+            .skip(query.offset.max(0) as usize)
+            .take(query.limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::search;
+    use crate::db::storage::suite;
+
+    macro_rules! suite {
+        ($($name:ident),*) => {
+            $(
+                #[test]
+                fn $name() {
+                    suite::$name(&MemoryStorage::new());
+                }
+            )*
+        };
+    }
+
+    suite!(
+        users,
+        rooms,
+        sanctions,
+        audit_logs,
+        blocks,
+        contacts,
+        contacts_only,
+        broadcasts,
+        accounts,
+        purge
+    );
+
+    fn contents(s: &MemoryStorage) -> Vec<String> {
+        let state = s.state().unwrap();
+        state.messages.iter().map(|m| m.content.clone()).collect()
+    }
+
+    #[test]
+    fn purge_applies_message_policy() {
+        for (policy, expected) in [
+            (MessagePolicy::Keep, vec!["secret", "reply"]),
+            (MessagePolicy::Redact, vec![REDACTED, "reply"]),
+            (MessagePolicy::Erase, vec!["reply"]),
+        ] {
+            let s = MemoryStorage::new();
+            s.add_user("alice".to_owned(), "passwd".to_owned()).unwrap();
+            s.add_user("bob".to_owned(), "passwd".to_owned()).unwrap();
+            let a = s.find_user_by_name("alice").unwrap().user_id;
+            let b = s.find_user_by_name("bob").unwrap().user_id;
+            let m = s.add_room_message(a, "rust", "secret").unwrap();
+            s.add_room_message(b, "rust", "reply").unwrap();
+            s.flag_message(m.message_id, "links", "link").unwrap();
+
+            s.delete_account(a, 0).unwrap();
+            s.purge_expired(policy).unwrap();
+            assert_eq!(contents(&s), expected);
+            let flags = s.state().unwrap().flags.len();
+            assert_eq!(flags, if policy == MessagePolicy::Erase { 0 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn search_ignores_operators() {
+        let s = MemoryStorage::new();
+        s.add_user("alice".to_owned(), "passwd".to_owned()).unwrap();
+        s.add_user("bob".to_owned(), "passwd".to_owned()).unwrap();
+        let a = s.find_user_by_name("alice").unwrap().user_id;
+        let b = s.find_user_by_name("bob").unwrap().user_id;
+        s.join_room(a, "rust").unwrap();
+        s.add_room_message(a, "rust", "Hello (world)").unwrap();
+        s.add_room_message(a, "rust", "other").unwrap();
+
+        let query = |input: &str| SearchQuery {
+            terms: search::terms(input),
+            room: None,
+            peer: None,
+            sender: None,
+            since: None,
+            until: None,
+            limit: 20,
+            offset: 0,
+        };
+        let found = s.search(a, &query("\"(hello* -+")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content, "Hello (world)");
+        assert!(s.search(b, &query("hello")).unwrap().is_empty());
+    }
+}
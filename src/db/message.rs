@@ -0,0 +1,171 @@
+#[cfg(feature = "sql")]
+use super::error::{deal_insert_result, deal_query_result, Error};
+#[cfg(feature = "sql")]
+use super::schema::{message_flags, messages};
+#[cfg(feature = "sql")]
+use super::Conn;
+use super::Tiny;
+use chrono::NaiveDateTime;
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+#[cfg(feature = "mysql")]
+use diesel::sql_types::{Bigint, Unsigned};
+use serde::Serialize;
+
+// 与 api::models 中的约定保持一致: 0 room, 1 p2p, 3 broadcast
+pub const ROOM_MESSAGE: Tiny = 0;
+pub const P2P_MESSAGE: Tiny = 1;
+pub const BROADCAST_MESSAGE: Tiny = 3;
+
+#[cfg(feature = "mysql")]
+no_arg_sql_function!(last_insert_id, Unsigned<Bigint>);
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryMessage {
+    pub message_id: i64,
+    pub from_user: i32,
+    pub message_type: Tiny,
+    pub room_name: Option<String>,
+    pub to_user: Option<i32>,
+    pub content: String,
+    pub create_time: NaiveDateTime,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "messages"]
+struct InsertableMessage<'a> {
+    from_user: i32,
+    message_type: Tiny,
+    room_name: Option<&'a str>,
+    to_user: Option<i32>,
+    content: &'a str,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "message_flags"]
+struct InsertableFlag<'a> {
+    message_id: i64,
+    filter_name: &'a str,
+    reason: &'a str,
+}
+
+#[cfg(feature = "sql")]
+#[cfg(feature = "mysql")]
+fn add(conn: &Conn, new_message: InsertableMessage) -> Result<QueryMessage, Error> {
+    use super::schema::messages::dsl::*;
+    let r = diesel::insert_into(messages)
+        .values(&new_message)
+        .execute(conn);
+    deal_insert_result(r)?;
+    // LAST_INSERT_ID() 按连接隔离, 必须和插入使用同一个连接
+    let id: QueryResult<u64> = diesel::select(last_insert_id).first(conn);
+    let id = deal_query_result(id)?;
+    let r: QueryResult<QueryMessage> = messages.find(id as i64).first(conn);
+    deal_query_result(r)
+}
+
+// Postgres 用 RETURNING 直接取回插入的行
+#[cfg(feature = "sql")]
+#[cfg(feature = "postgres")]
+fn add(conn: &Conn, new_message: InsertableMessage) -> Result<QueryMessage, Error> {
+    use super::schema::messages::dsl::*;
+    let r: QueryResult<QueryMessage> = diesel::insert_into(messages)
+        .values(&new_message)
+        .get_result(conn);
+    deal_query_result(r)
+}
+
+#[cfg(feature = "sql")]
+pub fn add_room_message(
+    conn: &Conn,
+    from: i32,
+    room: &str,
+    text: &str,
+) -> Result<QueryMessage, Error> {
+    add(
+        conn,
+        InsertableMessage {
+            from_user: from,
+            message_type: ROOM_MESSAGE,
+            room_name: Some(room),
+            to_user: None,
+            content: text,
+        },
+    )
+}
+
+#[cfg(feature = "sql")]
+pub fn add_p2p_message(conn: &Conn, from: i32, to: i32, text: &str) -> Result<QueryMessage, Error> {
+    add(
+        conn,
+        InsertableMessage {
+            from_user: from,
+            message_type: P2P_MESSAGE,
+            room_name: None,
+            to_user: Some(to),
+            content: text,
+        },
+    )
+}
+
+#[cfg(feature = "sql")]
+pub fn add_broadcast_message(conn: &Conn, from: i32, text: &str) -> Result<QueryMessage, Error> {
+    add(
+        conn,
+        InsertableMessage {
+            from_user: from,
+            message_type: BROADCAST_MESSAGE,
+            room_name: None,
+            to_user: None,
+            content: text,
+        },
+    )
+}
+
+// 用户离线期间(注册之后)的广播, 按时间先后, 最多 `max` 条
+#[cfg(feature = "sql")]
+pub fn pending_broadcasts(conn: &Conn, u_id: i32, max: i64) -> Result<Vec<QueryMessage>, Error> {
+    use super::schema::messages::dsl::*;
+    let u = super::user::find_with_id(conn, u_id)?;
+    let r: QueryResult<Vec<QueryMessage>> = messages
+        .filter(message_type.eq(BROADCAST_MESSAGE))
+        .filter(message_id.gt(u.last_broadcast_id))
+        .filter(create_time.ge(u.create_time))
+        .order(message_id.desc())
+        .limit(max)
+        .load(conn);
+    deal_query_result(r).map(|mut v| {
+        v.reverse();
+        v
+    })
+}
+
+// 记录被内容过滤标记的消息
+#[cfg(feature = "sql")]
+pub fn flag(conn: &Conn, m_id: i64, filter: &str, why: &str) -> Result<(), Error> {
+    let new_flag = InsertableFlag {
+        message_id: m_id,
+        filter_name: filter,
+        reason: why,
+    };
+    let r = diesel::insert_into(message_flags::table)
+        .values(&new_flag)
+        .execute(conn);
+    deal_insert_result(r)
+}
+
+// from 是否给 to 发过单聊消息
+#[cfg(feature = "sql")]
+pub fn has_p2p(conn: &Conn, from: i32, to: i32) -> Result<bool, Error> {
+    use super::schema::messages::dsl::*;
+    let r: QueryResult<i64> = messages
+        .filter(message_type.eq(P2P_MESSAGE))
+        .filter(from_user.eq(from))
+        .filter(to_user.eq(to))
+        .count()
+        .get_result(conn);
+    deal_query_result(r).map(|c| c > 0)
+}
@@ -0,0 +1,144 @@
+use super::error::Error;
+use super::Conn;
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+
+// 与 diesel CLI 使用同一张表, 两种方式执行的迁移可以混用
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (
+    version VARCHAR(50) PRIMARY KEY NOT NULL,
+    run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+)";
+
+pub struct Migration {
+    // 目录名, 如 `2020-03-30-073757_create_message`
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+impl Migration {
+    // diesel 的版本号: 目录名中 `_` 之前的部分去掉 `-`
+    pub fn version(&self) -> String {
+        self.name
+            .split('_')
+            .next()
+            .unwrap_or(self.name)
+            .replace('-', "")
+    }
+}
+
+#[cfg(feature = "mysql")]
+macro_rules! migration {
+    ($name:literal) => {
+        Migration {
+            name: $name,
+            up: include_str!(concat!("../../migrations/", $name, "/up.sql")),
+            down: include_str!(concat!("../../migrations/", $name, "/down.sql")),
+        }
+    };
+}
+
+#[cfg(feature = "postgres")]
+macro_rules! migration {
+    ($name:literal) => {
+        Migration {
+            name: $name,
+            up: include_str!(concat!("../../migrations_postgres/", $name, "/up.sql")),
+            down: include_str!(concat!("../../migrations_postgres/", $name, "/down.sql")),
+        }
+    };
+}
+
+// 新增迁移时需同时加到这里, 按时间先后
+pub const MIGRATIONS: &[Migration] = &[
+    migration!("2020-03-30-073757_create_message"),
+    migration!("2020-04-08-021530_create_messages"),
+    migration!("2020-04-12-083012_add_user_role"),
+    migration!("2020-04-15-024518_create_moderation"),
+    migration!("2020-04-18-063210_create_message_flags"),
+    migration!("2020-04-20-091402_create_user_blocks"),
+    migration!("2020-04-23-031755_create_contacts"),
+    migration!("2020-04-26-082033_add_user_profile"),
+    migration!("2020-04-29-071204_add_account_deletion"),
+];
+
+fn applied_versions(conn: &Conn) -> Result<Vec<String>, Error> {
+    use self::__diesel_schema_migrations::dsl::*;
+    conn.batch_execute(CREATE_MIGRATIONS_TABLE)?;
+    let r: QueryResult<Vec<String>> = __diesel_schema_migrations
+        .select(version)
+        .order(version)
+        .load(conn);
+    Ok(r?)
+}
+
+// 每个迁移及是否已执行
+pub fn status(conn: &Conn) -> Result<Vec<(&'static Migration, bool)>, Error> {
+    let applied = applied_versions(conn)?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| (m, applied.contains(&m.version())))
+        .collect())
+}
+
+// 执行所有未执行的迁移, 返回执行了的迁移
+pub fn run_pending(conn: &Conn) -> Result<Vec<&'static Migration>, Error> {
+    let mut done = Vec::new();
+    for (m, applied) in status(conn)? {
+        if !applied {
+            run(conn, m)?;
+            done.push(m);
+        }
+    }
+    Ok(done)
+}
+
+// 回滚最近执行的一个迁移, 没有已执行的迁移时为 None
+pub fn revert_latest(conn: &Conn) -> Result<Option<&'static Migration>, Error> {
+    let latest = status(conn)?
+        .into_iter()
+        .filter(|(_, applied)| *applied)
+        .map(|(m, _)| m)
+        .next_back();
+    match latest {
+        Some(m) => revert(conn, m).map(|_| Some(m)),
+        None => Ok(None),
+    }
+}
+
+// 回滚并重新执行最近的一个迁移
+pub fn redo(conn: &Conn) -> Result<Option<&'static Migration>, Error> {
+    match revert_latest(conn)? {
+        Some(m) => run(conn, m).map(|_| Some(m)),
+        None => Ok(None),
+    }
+}
+
+fn run(conn: &Conn, m: &Migration) -> Result<(), Error> {
+    use self::__diesel_schema_migrations::dsl::*;
+    let r = conn.transaction::<_, diesel::result::Error, _>(|| {
+        conn.batch_execute(m.up)?;
+        diesel::insert_into(__diesel_schema_migrations)
+            .values(version.eq(m.version()))
+            .execute(conn)?;
+        Ok(())
+    });
+    r.map_err(|e| Error::WapperError(format!("migration {} failed: {}", m.name, e)))
+}
+
+fn revert(conn: &Conn, m: &Migration) -> Result<(), Error> {
+    use self::__diesel_schema_migrations::dsl::*;
+    let r = conn.transaction::<_, diesel::result::Error, _>(|| {
+        conn.batch_execute(m.down)?;
+        diesel::delete(__diesel_schema_migrations.find(m.version())).execute(conn)?;
+        Ok(())
+    });
+    r.map_err(|e| Error::WapperError(format!("reverting {} failed: {}", m.name, e)))
+}
@@ -1,15 +1,140 @@
-extern crate dotenv;
-use diesel::mysql::MysqlConnection;
-use diesel::prelude::*;
-use std::env;
+#[cfg(feature = "sql")]
+use crate::metrics::METRICS;
+use actix_web::web;
+#[cfg(feature = "sql")]
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use error::Error;
+use r2d2_redis::{r2d2, RedisConnectionManager};
+use std::sync::Arc;
+use storage::Storage;
+#[cfg(feature = "sql")]
+use tracing::debug_span;
+use tracing::Span;
 
+pub mod account;
+pub mod block;
+pub mod contact;
 pub mod error;
+pub mod memory;
+pub mod message;
+#[cfg(feature = "sql")]
+pub mod migrate;
+pub mod moderation;
+pub mod room;
+#[cfg_attr(feature = "postgres", path = "schema_pg.rs")]
 pub mod schema;
+pub mod search;
+pub mod session;
+pub mod storage;
 pub mod user;
 
-pub fn establish_connection() -> MysqlConnection {
-    let _ = dotenv::dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    MysqlConnection::establish(&database_url)
-        .expect(&format!("Error connecting to {}", database_url))
+#[cfg(all(feature = "mysql", feature = "postgres"))]
+compile_error!("features `mysql` and `postgres` are mutually exclusive");
+
+// 数据库后端在编译时由 feature 选定, `db` 模块的查询函数只依赖这几个别名.
+// 两个都不选时不编译查询函数, 只能使用内存存储
+#[cfg(feature = "mysql")]
+pub type Conn = diesel::mysql::MysqlConnection;
+#[cfg(feature = "postgres")]
+pub type Conn = diesel::pg::PgConnection;
+
+// TINYINT 列, Postgres 没有单字节整数, 用 SMALLINT
+#[cfg(not(feature = "postgres"))]
+pub type Tiny = i8;
+#[cfg(feature = "postgres")]
+pub type Tiny = i16;
+
+pub type RedisPool = r2d2::Pool<RedisConnectionManager>;
+#[cfg(feature = "sql")]
+pub type DbPool = diesel::r2d2::Pool<ConnectionManager<Conn>>;
+
+// 处理函数访问存储的入口, 作为 app data 共享
+// `store` 调用配置的 `Storage` 后端; `run` 执行 `db` 模块的 SQL 查询, 只用于
+// 数据库特有的检查 (迁移), 仅在 sql feature 下存在. 两者都在阻塞线程池上运行
+#[derive(Clone)]
+pub struct Repository {
+    storage: Arc<dyn Storage>,
+    #[cfg(feature = "sql")]
+    pool: Option<DbPool>,
+}
+
+impl Repository {
+    pub fn new(storage: Arc<dyn Storage>) -> Repository {
+        Repository {
+            storage,
+            #[cfg(feature = "sql")]
+            pool: None,
+        }
+    }
+
+    // 使用数据库时带上连接池, `run` 和 `conn` 从中取连接
+    #[cfg(feature = "sql")]
+    pub fn with_pool(mut self, pool: DbPool) -> Repository {
+        self.pool = Some(pool);
+        self
+    }
+
+    // 连接池的使用情况, 不连接数据库时为 None
+    #[cfg(feature = "sql")]
+    pub fn pool_state(&self) -> Option<r2d2::State> {
+        self.pool.as_ref().map(|pool| pool.state())
+    }
+
+    #[cfg(not(feature = "sql"))]
+    pub fn pool_state(&self) -> Option<r2d2::State> {
+        None
+    }
+
+    // 在当前线程上取连接, 只用于已在线程池上运行的代码(搜索引擎)
+    #[cfg(feature = "sql")]
+    pub fn conn(&self) -> Result<PooledConnection<ConnectionManager<Conn>>, Error> {
+        match self.pool {
+            Some(ref pool) => pool.get().map_err(|e| Error::WapperError(e.to_string())),
+            None => Err(Error::Unsupported),
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    pub async fn run<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Conn) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone().ok_or(Error::Unsupported)?;
+        // 在线程池上运行, 需要显式带上调用方的 span
+        let span = debug_span!("db", kind = "query");
+        blocking(move || {
+            let _enter = span.enter();
+            let _timer = METRICS
+                .db_duration
+                .with_label_values(&["query"])
+                .start_timer();
+            let conn = pool.get().map_err(|e| Error::WapperError(e.to_string()))?;
+            f(&conn)
+        })
+        .await
+    }
+
+    pub async fn store<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&dyn Storage) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let storage = self.storage.clone();
+        let span = Span::current();
+        blocking(move || {
+            let _enter = span.enter();
+            f(storage.as_ref())
+        })
+        .await
+    }
+}
+
+// 在线程池上运行阻塞调用 (数据库, Redis, 文件等)
+pub async fn blocking<F, T>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await.map_err(Error::from)
 }
@@ -0,0 +1,154 @@
+#[cfg(feature = "sql")]
+use super::error::{deal_insert_result, deal_query_result, Error};
+#[cfg(feature = "sql")]
+use super::schema::{audit_logs, sanctions};
+#[cfg(feature = "sql")]
+use super::Conn;
+#[cfg(feature = "sql")]
+use super::Tiny;
+use chrono::{NaiveDateTime, Utc};
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SanctionKind {
+    Mute = 0,
+    Ban = 1,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "sanctions"]
+struct InsertableSanction<'a> {
+    room_name: Option<&'a str>,
+    user_id: i32,
+    kind: Tiny,
+    expire_time: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryAuditLog {
+    pub log_id: i64,
+    pub operator: i32,
+    pub action: String,
+    pub target_user: i32,
+    pub room_name: Option<String>,
+    pub detail: Option<String>,
+    pub create_time: NaiveDateTime,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "audit_logs"]
+struct InsertableAuditLog<'a> {
+    operator: i32,
+    action: &'a str,
+    target_user: i32,
+    room_name: Option<&'a str>,
+    detail: Option<&'a str>,
+}
+
+// 过期时间都按 UTC 记录和比较
+pub fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// room 为 None 时为全站范围; 同一范围内的同类处罚以最后一次为准,
+// 删除旧处罚和写入新处罚在同一事务中, 写入失败时保留旧处罚
+#[cfg(feature = "sql")]
+pub fn add(
+    conn: &Conn,
+    room: Option<&str>,
+    u_id: i32,
+    sanction: SanctionKind,
+    expire: Option<NaiveDateTime>,
+) -> Result<(), Error> {
+    conn.transaction(|| {
+        remove(conn, room, u_id, sanction)?;
+        let new_sanction = InsertableSanction {
+            room_name: room,
+            user_id: u_id,
+            kind: sanction as Tiny,
+            expire_time: expire,
+        };
+        let r = diesel::insert_into(sanctions::table)
+            .values(&new_sanction)
+            .execute(conn);
+        deal_insert_result(r)
+    })
+}
+
+#[cfg(feature = "sql")]
+pub fn remove(
+    conn: &Conn,
+    room: Option<&str>,
+    u_id: i32,
+    sanction: SanctionKind,
+) -> Result<(), Error> {
+    use super::schema::sanctions::dsl::*;
+    let target = sanctions
+        .filter(user_id.eq(u_id))
+        .filter(kind.eq(sanction as Tiny));
+    let r = match room {
+        Some(room) => diesel::delete(target.filter(room_name.eq(room))).execute(conn),
+        None => diesel::delete(target.filter(room_name.is_null())).execute(conn),
+    };
+    deal_query_result(r).map(|_| ())
+}
+
+// 房间内的检查同时考虑全站处罚
+#[cfg(feature = "sql")]
+pub fn is_active(
+    conn: &Conn,
+    room: Option<&str>,
+    u_id: i32,
+    sanction: SanctionKind,
+) -> Result<bool, Error> {
+    use super::schema::sanctions::dsl::*;
+    let mut q = sanctions
+        .filter(user_id.eq(u_id))
+        .filter(kind.eq(sanction as Tiny))
+        .filter(expire_time.is_null().or(expire_time.gt(now())))
+        .into_boxed();
+    q = match room {
+        Some(room) => q.filter(room_name.is_null().or(room_name.eq(room))),
+        None => q.filter(room_name.is_null()),
+    };
+    let r: QueryResult<i64> = q.count().get_result(conn);
+    deal_query_result(r).map(|c| c > 0)
+}
+
+#[cfg(feature = "sql")]
+pub fn audit(
+    conn: &Conn,
+    operator_id: i32,
+    action_name: &str,
+    target: i32,
+    room: Option<&str>,
+    detail_text: Option<&str>,
+) -> Result<(), Error> {
+    let log = InsertableAuditLog {
+        operator: operator_id,
+        action: action_name,
+        target_user: target,
+        room_name: room,
+        detail: detail_text,
+    };
+    let r = diesel::insert_into(audit_logs::table)
+        .values(&log)
+        .execute(conn);
+    deal_insert_result(r)
+}
+
+#[cfg(feature = "sql")]
+pub fn audit_logs(conn: &Conn, limit: i64, offset: i64) -> Result<Vec<QueryAuditLog>, Error> {
+    use super::schema::audit_logs::dsl::*;
+    let r: QueryResult<Vec<QueryAuditLog>> = audit_logs
+        .order(log_id.desc())
+        .limit(limit)
+        .offset(offset)
+        .load(conn);
+    deal_query_result(r)
+}
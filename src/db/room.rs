@@ -0,0 +1,126 @@
+#[cfg(feature = "sql")]
+use super::error::{deal_query_result, Error};
+#[cfg(feature = "sql")]
+use super::schema::room_members;
+#[cfg(feature = "sql")]
+use super::Conn;
+use super::Tiny;
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum RoomRole {
+    Member = 0,
+    Manager = 1,
+    Owner = 2,
+}
+
+impl From<Tiny> for RoomRole {
+    fn from(v: Tiny) -> RoomRole {
+        match v {
+            2 => RoomRole::Owner,
+            1 => RoomRole::Manager,
+            _ => RoomRole::Member,
+        }
+    }
+}
+
+#[cfg(feature = "sql")]
+#[derive(Insertable)]
+#[table_name = "room_members"]
+struct InsertableMember<'a> {
+    room_name: &'a str,
+    user_id: i32,
+    role: Tiny,
+}
+
+// 记录成员加入房间, 加入不会得到房主身份: 房间被清空后(如成员都被踢出)
+// 再加入的用户不能因此成为房主
+#[cfg(feature = "sql")]
+pub fn join(conn: &Conn, u_id: i32, room: &str) -> Result<(), Error> {
+    insert(conn, u_id, room, RoomRole::Member)
+}
+
+#[cfg(feature = "sql")]
+fn insert(conn: &Conn, u_id: i32, room: &str, member_role: RoomRole) -> Result<(), Error> {
+    use super::schema::room_members::dsl::*;
+    let member = InsertableMember {
+        room_name: room,
+        user_id: u_id,
+        role: member_role as Tiny,
+    };
+    #[cfg(feature = "mysql")]
+    let r = diesel::insert_or_ignore_into(room_members)
+        .values(&member)
+        .execute(conn);
+    #[cfg(feature = "postgres")]
+    let r = diesel::insert_into(room_members)
+        .values(&member)
+        .on_conflict_do_nothing()
+        .execute(conn);
+    deal_query_result(r).map(|_| ())
+}
+
+// 以房主身份加入一个还没有成员的房间, 房间已存在时返回 DuplicateData
+#[cfg(feature = "sql")]
+pub fn create(conn: &Conn, u_id: i32, room: &str) -> Result<(), Error> {
+    use super::schema::room_members::dsl::*;
+    conn.transaction(|| {
+        let count: QueryResult<i64> = room_members
+            .filter(room_name.eq(room))
+            .count()
+            .get_result(conn);
+        if deal_query_result(count)? > 0 {
+            return Err(Error::DuplicateData("room_name".to_owned()));
+        }
+        insert(conn, u_id, room, RoomRole::Owner)
+    })
+}
+
+// 所有有成员的房间及成员数, 按房间名排序
+#[cfg(feature = "sql")]
+pub fn list(conn: &Conn) -> Result<Vec<(String, i64)>, Error> {
+    use super::schema::room_members::dsl::*;
+    // diesel 1.x 的 select 不能混用聚合和普通列, 在这里计数
+    let r: QueryResult<Vec<String>> = room_members.select(room_name).order(room_name).load(conn);
+    let mut rooms: Vec<(String, i64)> = Vec::new();
+    for name in deal_query_result(r)? {
+        match rooms.last_mut() {
+            Some((last, count)) if *last == name => *count += 1,
+            _ => rooms.push((name, 1)),
+        }
+    }
+    Ok(rooms)
+}
+
+#[cfg(feature = "sql")]
+pub fn leave(conn: &Conn, u_id: i32, room: &str) -> Result<(), Error> {
+    use super::schema::room_members::dsl::*;
+    let r = diesel::delete(room_members.find((room, u_id))).execute(conn);
+    deal_query_result(r).map(|_| ())
+}
+
+// 不是房间成员时为 None
+#[cfg(feature = "sql")]
+pub fn role_of(conn: &Conn, u_id: i32, room: &str) -> Result<Option<RoomRole>, Error> {
+    use super::schema::room_members::dsl::*;
+    let r: QueryResult<Option<Tiny>> = room_members
+        .find((room, u_id))
+        .select(role)
+        .first(conn)
+        .optional();
+    deal_query_result(r).map(|v| v.map(RoomRole::from))
+}
+
+// 不是房间成员时返回 NotFound; 角色没有变化时 MySQL 的影响行数为 0, 不能据此判断
+#[cfg(feature = "sql")]
+pub fn set_role(conn: &Conn, u_id: i32, room: &str, member_role: RoomRole) -> Result<(), Error> {
+    use super::schema::room_members::dsl::*;
+    if role_of(conn, u_id, room)?.is_none() {
+        return Err(Error::NotFound);
+    }
+    let r = diesel::update(room_members.find((room, u_id)))
+        .set(role.eq(member_role as Tiny))
+        .execute(conn);
+    deal_query_result(r).map(|_| ())
+}
@@ -1,3 +1,85 @@
+table! {
+    audit_logs (log_id) {
+        log_id -> Bigint,
+        operator -> Integer,
+        action -> Varchar,
+        target_user -> Integer,
+        room_name -> Nullable<Varchar>,
+        detail -> Nullable<Varchar>,
+        create_time -> Timestamp,
+    }
+}
+
+table! {
+    contact_requests (request_id) {
+        request_id -> Bigint,
+        from_user -> Integer,
+        to_user -> Integer,
+        state -> Tinyint,
+        message -> Nullable<Varchar>,
+        create_time -> Timestamp,
+        updata_time -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    contacts (user_id, contact_id) {
+        user_id -> Integer,
+        contact_id -> Integer,
+        create_time -> Timestamp,
+    }
+}
+
+table! {
+    message_flags (flag_id) {
+        flag_id -> Bigint,
+        message_id -> Bigint,
+        filter_name -> Varchar,
+        reason -> Varchar,
+        create_time -> Timestamp,
+    }
+}
+
+table! {
+    messages (message_id) {
+        message_id -> Bigint,
+        from_user -> Integer,
+        message_type -> Tinyint,
+        room_name -> Nullable<Varchar>,
+        to_user -> Nullable<Integer>,
+        content -> Text,
+        create_time -> Timestamp,
+    }
+}
+
+table! {
+    room_members (room_name, user_id) {
+        room_name -> Varchar,
+        user_id -> Integer,
+        join_time -> Timestamp,
+        role -> Tinyint,
+    }
+}
+
+table! {
+    sanctions (sanction_id) {
+        sanction_id -> Bigint,
+        room_name -> Nullable<Varchar>,
+        user_id -> Integer,
+        kind -> Tinyint,
+        expire_time -> Nullable<Timestamp>,
+        create_time -> Timestamp,
+    }
+}
+
+table! {
+    user_blocks (blocker, blocked) {
+        blocker -> Integer,
+        blocked -> Integer,
+        create_time -> Timestamp,
+    }
+}
+
 table! {
     users (user_id) {
         user_id -> Integer,
@@ -6,5 +88,32 @@ table! {
         create_time -> Timestamp,
         updata_time -> Nullable<Timestamp>,
         delete_time -> Nullable<Timestamp>,
+        role -> Tinyint,
+        last_broadcast_id -> Bigint,
+        contacts_only -> Bool,
+        display_name -> Nullable<Varchar>,
+        avatar -> Nullable<Varchar>,
+        email -> Nullable<Varchar>,
+        phone -> Nullable<Varchar>,
+        bio -> Nullable<Varchar>,
+        purge_time -> Nullable<Timestamp>,
+        purged -> Bool,
     }
 }
+
+joinable!(message_flags -> messages (message_id));
+joinable!(messages -> users (from_user));
+joinable!(room_members -> users (user_id));
+joinable!(sanctions -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    audit_logs,
+    contact_requests,
+    contacts,
+    message_flags,
+    messages,
+    room_members,
+    sanctions,
+    user_blocks,
+    users,
+);
@@ -0,0 +1,184 @@
+#[cfg(feature = "sql")]
+use super::error::deal_query_result;
+use super::error::Error;
+use super::message::QueryMessage;
+#[cfg(feature = "sql")]
+use super::message::{BROADCAST_MESSAGE, P2P_MESSAGE, ROOM_MESSAGE};
+#[cfg(feature = "sql")]
+use super::Repository;
+use chrono::NaiveDateTime;
+#[cfg(feature = "sql")]
+use diesel::dsl::sql;
+#[cfg(feature = "sql")]
+use diesel::prelude::*;
+#[cfg(feature = "postgres")]
+use diesel::sql_types::Array;
+#[cfg(feature = "sql")]
+use diesel::sql_types::{Bool, Text};
+
+pub struct SearchQuery {
+    // `terms` 的结果, 不为空
+    pub terms: Vec<String>,
+    pub room: Option<String>,
+    pub peer: Option<i32>,
+    pub sender: Option<i32>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// 已落库消息的全文搜索. 只返回 `reader` 能看到的消息: 已加入房间的消息,
+// 收发的单聊消息和广播
+pub trait SearchEngine: Send + Sync {
+    // 消息落库后调用, 使用数据库自身索引的实现可以不做处理
+    fn index(&self, message: &QueryMessage) -> Result<(), Error>;
+
+    fn search(&self, reader: i32, query: &SearchQuery) -> Result<Vec<QueryMessage>, Error>;
+}
+
+// 使用数据库自己在 `messages.content` 上的索引: MySQL 为 boolean 模式的 `FULLTEXT`
+// 索引 (ngram 分词), Postgres 为 `pg_trgm` 索引加 `ILIKE`, 匹配任一词即可
+#[cfg(feature = "sql")]
+pub struct SqlFulltext {
+    repo: Repository,
+}
+
+#[cfg(feature = "sql")]
+impl SqlFulltext {
+    pub fn new(repo: Repository) -> SqlFulltext {
+        SqlFulltext { repo }
+    }
+}
+
+// 按空白拆分关键词, 去掉 MySQL 布尔模式的运算符, 用户输入不能改变查询的语法
+pub fn terms(input: &str) -> Vec<String> {
+    input
+        .split_whitespace()
+        .map(|t| t.replace('"', ""))
+        .map(|t| t.trim_matches(|c| "+-*()<>~@".contains(c)).to_owned())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+// 每个关键词作为短语加引号, 任一命中即可
+#[cfg(feature = "mysql")]
+fn boolean_query(terms: &[String]) -> String {
+    terms
+        .iter()
+        .map(|t| format!("\"{}\"", t))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// 转义 LIKE 通配符
+#[cfg(feature = "postgres")]
+fn like_patterns(terms: &[String]) -> Vec<String> {
+    terms
+        .iter()
+        .map(|t| {
+            let escaped = t
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+        .collect()
+}
+
+#[cfg(feature = "sql")]
+impl SearchEngine for SqlFulltext {
+    fn index(&self, _: &QueryMessage) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn search(&self, reader: i32, query: &SearchQuery) -> Result<Vec<QueryMessage>, Error> {
+        use super::schema::messages::dsl::*;
+        use super::schema::room_members;
+
+        let connection = self.repo.conn()?;
+        let joined_rooms = room_members::table
+            .filter(room_members::user_id.eq(reader))
+            .select(room_members::room_name.nullable());
+        let mut q = messages
+            .filter(
+                message_type
+                    .eq(ROOM_MESSAGE)
+                    .and(room_name.eq_any(joined_rooms))
+                    .or(message_type
+                        .eq(P2P_MESSAGE)
+                        .and(from_user.eq(reader).or(to_user.eq(reader))))
+                    .or(message_type.eq(BROADCAST_MESSAGE)),
+            )
+            .into_boxed();
+        #[cfg(feature = "mysql")]
+        let matches = sql::<Bool>("MATCH (content) AGAINST (")
+            .bind::<Text, _>(boolean_query(&query.terms))
+            .sql(" IN BOOLEAN MODE)");
+        #[cfg(feature = "postgres")]
+        let matches = sql::<Bool>("content ILIKE ANY (")
+            .bind::<Array<Text>, _>(like_patterns(&query.terms))
+            .sql(")");
+        q = q.filter(matches);
+
+        if let Some(ref room) = query.room {
+            q = q
+                .filter(message_type.eq(ROOM_MESSAGE))
+                .filter(room_name.eq(room.clone()));
+        }
+        if let Some(peer) = query.peer {
+            q = q.filter(message_type.eq(P2P_MESSAGE)).filter(
+                from_user
+                    .eq(reader)
+                    .and(to_user.eq(peer))
+                    .or(from_user.eq(peer).and(to_user.eq(reader))),
+            );
+        }
+        if let Some(sender) = query.sender {
+            q = q.filter(from_user.eq(sender));
+        }
+        if let Some(since) = query.since {
+            q = q.filter(create_time.ge(since));
+        }
+        if let Some(until) = query.until {
+            q = q.filter(create_time.le(until));
+        }
+
+        let r: QueryResult<Vec<QueryMessage>> = q
+            .order(create_time.desc())
+            .limit(query.limit)
+            .offset(query.offset)
+            .load(&*connection);
+        deal_query_result(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terms_drop_operators() {
+        assert_eq!(terms("  rust  chat "), vec!["rust", "chat"]);
+        assert_eq!(
+            terms("+rust -chat* ~a <b> @c"),
+            vec!["rust", "chat", "a", "b", "c"]
+        );
+        assert_eq!(terms("\"rust chat\" (x)"), vec!["rust", "chat", "x"]);
+        assert_eq!(terms("say\"hi\""), vec!["sayhi"]);
+        assert!(terms("\" ( * ) -").is_empty());
+    }
+
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn boolean_query_quotes_terms() {
+        let q = boolean_query(&terms("(a+b c*"));
+        assert_eq!(q, "\"a+b\" \"c\"");
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn like_patterns_escape_wildcards() {
+        assert_eq!(like_patterns(&terms("50% a_b")), vec!["%50\\%%", "%a\\_b%"]);
+    }
+}
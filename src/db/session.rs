@@ -0,0 +1,115 @@
+use super::error::Error;
+use super::RedisPool;
+use crate::metrics::METRICS;
+use r2d2_redis::redis::{self, Commands};
+use rand::{distributions::Alphanumeric, Rng};
+
+// 登录态有效期 7 天
+const SESSION_TTL: usize = 7 * 24 * 60 * 60;
+// 重置密码链接有效期 30 分钟
+const RESET_TTL: usize = 30 * 60;
+
+fn session_key(token: &str) -> String {
+    format!("session:{}", token)
+}
+
+// 用户的全部 token, 用于修改密码后注销登录态
+fn user_sessions_key(u_id: i32) -> String {
+    format!("user_sessions:{}", u_id)
+}
+
+fn reset_key(token: &str) -> String {
+    format!("reset:{}", token)
+}
+
+fn deal_redis_result<T>(r: redis::RedisResult<T>) -> Result<T, Error> {
+    r.map_err(|e| Error::WapperError(e.to_string()))
+}
+
+fn new_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .collect()
+}
+
+pub fn create(pool: &RedisPool, u_id: i32) -> Result<String, Error> {
+    let _timer = METRICS
+        .redis_duration
+        .with_label_values(&["session_create"])
+        .start_timer();
+    let mut conn = pool.get().map_err(|e| Error::WapperError(e.to_string()))?;
+    let token = new_token();
+    let r: redis::RedisResult<()> = redis::pipe()
+        .atomic()
+        .set_ex(session_key(&token), u_id, SESSION_TTL)
+        .ignore()
+        .sadd(user_sessions_key(u_id), &token)
+        .ignore()
+        .expire(user_sessions_key(u_id), SESSION_TTL)
+        .ignore()
+        .query(&mut *conn);
+    deal_redis_result(r)?;
+    Ok(token)
+}
+
+pub fn user_of(pool: &RedisPool, token: &str) -> Result<i32, Error> {
+    let _timer = METRICS
+        .redis_duration
+        .with_label_values(&["session_user_of"])
+        .start_timer();
+    let mut conn = pool.get().map_err(|e| Error::WapperError(e.to_string()))?;
+    let r: redis::RedisResult<Option<i32>> = conn.get(session_key(token));
+    match deal_redis_result(r)? {
+        Some(u_id) => Ok(u_id),
+        None => Err(Error::NotFound),
+    }
+}
+
+// 注销用户的所有登录态
+pub fn revoke_all(pool: &RedisPool, u_id: i32) -> Result<(), Error> {
+    let _timer = METRICS
+        .redis_duration
+        .with_label_values(&["session_revoke_all"])
+        .start_timer();
+    let mut conn = pool.get().map_err(|e| Error::WapperError(e.to_string()))?;
+    let r: redis::RedisResult<Vec<String>> = conn.smembers(user_sessions_key(u_id));
+    let mut keys: Vec<String> = deal_redis_result(r)?
+        .iter()
+        .map(|token| session_key(token))
+        .collect();
+    keys.push(user_sessions_key(u_id));
+    let r: redis::RedisResult<()> = conn.del(keys);
+    deal_redis_result(r)
+}
+
+pub fn create_reset(pool: &RedisPool, u_id: i32) -> Result<String, Error> {
+    let _timer = METRICS
+        .redis_duration
+        .with_label_values(&["session_create_reset"])
+        .start_timer();
+    let mut conn = pool.get().map_err(|e| Error::WapperError(e.to_string()))?;
+    let token = new_token();
+    let r: redis::RedisResult<()> = conn.set_ex(reset_key(&token), u_id, RESET_TTL);
+    deal_redis_result(r)?;
+    Ok(token)
+}
+
+// 取出并作废重置 token, 只能使用一次
+pub fn take_reset(pool: &RedisPool, token: &str) -> Result<i32, Error> {
+    let _timer = METRICS
+        .redis_duration
+        .with_label_values(&["session_take_reset"])
+        .start_timer();
+    let mut conn = pool.get().map_err(|e| Error::WapperError(e.to_string()))?;
+    let r: redis::RedisResult<(Option<i32>,)> = redis::pipe()
+        .atomic()
+        .get(reset_key(token))
+        .del(reset_key(token))
+        .ignore()
+        .query(&mut *conn);
+    match deal_redis_result(r)? {
+        (Some(u_id),) => Ok(u_id),
+        (None,) => Err(Error::NotFound),
+    }
+}
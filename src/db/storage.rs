@@ -0,0 +1,682 @@
+use super::account::MessagePolicy;
+use super::block::QueryBlock;
+use super::contact::QueryContactRequest;
+use super::error::Error;
+use super::message::QueryMessage;
+use super::moderation::{QueryAuditLog, SanctionKind};
+use super::room::RoomRole;
+use super::user::{ProfileChangeset, QueryProfile, QueryUser, Role, UserSummary};
+use super::Tiny;
+#[cfg(feature = "sql")]
+use super::{account, block, contact, message, moderation, room, user, Conn, DbPool};
+#[cfg(feature = "sql")]
+use crate::metrics::METRICS;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sql")]
+use tracing::debug_span;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    // 编译时选定的数据库, MySQL 或 Postgres
+    #[serde(alias = "mysql", alias = "postgres")]
+    Sql,
+    Memory,
+}
+
+impl StorageKind {
+    // `sql` 或 `memory`, `mysql` 和 `postgres` 是 `sql` 的别名
+    pub fn parse(value: &str) -> Option<StorageKind> {
+        match value {
+            "sql" | "mysql" | "postgres" => Some(StorageKind::Sql),
+            "memory" => Some(StorageKind::Memory),
+            _ => None,
+        }
+    }
+}
+
+// 用户及账号, 房间, 消息, 广播回执, 处罚, 审计日志, 屏蔽和联系人的存储
+// 调用是阻塞的, 处理函数通过 `Repository::store` 在阻塞线程池上调用.
+// 查找用户时不返回停用或注销的账号
+pub trait Storage: Send + Sync {
+    fn add_user(&self, name: String, passwd: String) -> Result<(), Error>;
+    fn verify_user(&self, name: &str, passwd: &str) -> Result<QueryUser, Error>;
+    fn find_user(&self, user_id: i32) -> Result<QueryUser, Error>;
+    fn find_user_by_name(&self, name: &str) -> Result<QueryUser, Error>;
+    fn find_user_by_email(&self, email: &str) -> Result<QueryUser, Error>;
+    fn user_names(&self, user_ids: &[i32]) -> Result<Vec<(i32, String)>, Error>;
+    // `old` 不匹配时返回 NotFound
+    fn change_passwd(&self, user_id: i32, old: &str, new: &str) -> Result<(), Error>;
+    fn set_passwd(&self, user_id: i32, new: &str) -> Result<(), Error>;
+    fn set_contacts_only(&self, user_id: i32, only: bool) -> Result<(), Error>;
+    fn profile(&self, user_id: i32) -> Result<QueryProfile, Error>;
+    // 邮箱或手机号已被使用时返回 DuplicateData("email" | "phone")
+    fn update_profile(&self, user_id: i32, changes: &ProfileChangeset) -> Result<(), Error>;
+    // 账号已停用或注销时返回 NotFound
+    fn set_role(&self, user_id: i32, role: Role) -> Result<(), Error>;
+    // 除已清除外的所有账号, 含停用和注销的, 按 id 排序
+    fn users(&self, limit: i64, offset: i64) -> Result<Vec<UserSummary>, Error>;
+
+    // 停用的账号随时可以恢复, 已停用或注销时返回 NotFound
+    fn deactivate(&self, user_id: i32) -> Result<(), Error>;
+    // 返回可以恢复的截止时间
+    fn delete_account(&self, user_id: i32, restore_days: i64) -> Result<NaiveDateTime, Error>;
+    // 用户名和密码匹配的停用账号, 或仍在恢复期内的注销账号
+    fn find_restorable(&self, name: &str, passwd: &str) -> Result<QueryUser, Error>;
+    // 账号已清除时返回 NotFound
+    fn restore(&self, user_id: i32) -> Result<(), Error>;
+    // 匿名化超过恢复期的注销账号, 删除其房间, 联系人和屏蔽, 按 `policy` 处理其消息.
+    // 返回清除的账号数
+    fn purge_expired(&self, policy: MessagePolicy) -> Result<usize, Error>;
+
+    // 以普通成员加入, 只有 `rust_chat room create` 创建的房间有房主
+    fn join_room(&self, user_id: i32, room: &str) -> Result<(), Error>;
+    fn leave_room(&self, user_id: i32, room: &str) -> Result<(), Error>;
+    fn room_role(&self, user_id: i32, room: &str) -> Result<Option<RoomRole>, Error>;
+    // 不是房间成员时返回 NotFound
+    fn set_room_role(&self, user_id: i32, room: &str, role: RoomRole) -> Result<(), Error>;
+    // 有成员的房间及成员数, 按名称排序
+    fn room_members(&self) -> Result<Vec<(String, i64)>, Error>;
+
+    // 已经屏蔽时返回 DuplicateData
+    fn add_block(&self, user_id: i32, other: i32) -> Result<(), Error>;
+    // 没有屏蔽时返回 NotFound
+    fn remove_block(&self, user_id: i32, other: i32) -> Result<(), Error>;
+    // `user_id` 屏蔽的用户, 新的在前
+    fn blocks(&self, user_id: i32) -> Result<Vec<QueryBlock>, Error>;
+    // `user_id` 是否屏蔽了 `other`
+    fn is_blocked(&self, user_id: i32, other: i32) -> Result<bool, Error>;
+
+    // 两人之间已有的请求重置为待处理
+    fn contact_request(
+        &self,
+        from: i32,
+        to: i32,
+        message: Option<&str>,
+    ) -> Result<QueryContactRequest, Error>;
+    fn find_contact_request(&self, request_id: i64) -> Result<QueryContactRequest, Error>;
+    // 发给 `user_id` 的待处理请求, 新的在前
+    fn pending_contact_requests(&self, user_id: i32) -> Result<Vec<QueryContactRequest>, Error>;
+    // 接受后双方成为联系人, 请求不是待处理时返回 NotFound
+    fn answer_contact_request(&self, request_id: i64, state: Tiny) -> Result<(), Error>;
+    // 不是联系人时返回 NotFound
+    fn remove_contact(&self, user_id: i32, other: i32) -> Result<(), Error>;
+    fn contacts(&self, user_id: i32) -> Result<Vec<i32>, Error>;
+    fn are_contacts(&self, user_id: i32, other: i32) -> Result<bool, Error>;
+    // `to` 只接收联系人的私聊, 而 `from` 既不是联系人也不是在回复 `to` 时为 false
+    fn may_message(&self, from: i32, to: i32) -> Result<bool, Error>;
+
+    // `room` 为 None 时全站生效. 替换同一范围内同类的处罚, `expire` 为 None 时永久
+    fn add_sanction(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+        expire: Option<NaiveDateTime>,
+    ) -> Result<(), Error>;
+    fn remove_sanction(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+    ) -> Result<(), Error>;
+    // 是否有未过期的处罚, 检查房间时也算全站处罚
+    fn is_sanctioned(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+    ) -> Result<bool, Error>;
+    fn audit(
+        &self,
+        operator: i32,
+        action: &str,
+        target: i32,
+        room: Option<&str>,
+        detail: Option<&str>,
+    ) -> Result<(), Error>;
+    // 新的在前
+    fn audit_logs(&self, limit: i64, offset: i64) -> Result<Vec<QueryAuditLog>, Error>;
+
+    fn add_room_message(&self, from: i32, room: &str, text: &str) -> Result<QueryMessage, Error>;
+    fn add_p2p_message(&self, from: i32, to: i32, text: &str) -> Result<QueryMessage, Error>;
+    fn add_broadcast_message(&self, from: i32, text: &str) -> Result<QueryMessage, Error>;
+    fn flag_message(&self, message_id: i64, filter: &str, reason: &str) -> Result<(), Error>;
+
+    // 用户注册以来尚未投递的广播, 旧的在前, 最多 `max` 条
+    fn pending_broadcasts(&self, user_id: i32, max: i64) -> Result<Vec<QueryMessage>, Error>;
+    fn mark_broadcast_read(&self, user_ids: &[i32], message_id: i64) -> Result<(), Error>;
+}
+
+// 基于数据库连接池的 `Storage` (MySQL 或 Postgres, 由 cargo feature 选定),
+// 使用 `db` 各模块的查询函数
+#[cfg(feature = "sql")]
+pub struct SqlStorage {
+    pool: DbPool,
+}
+
+#[cfg(feature = "sql")]
+impl SqlStorage {
+    pub fn new(pool: DbPool) -> SqlStorage {
+        SqlStorage { pool }
+    }
+
+    // `op` 为 Storage 的方法名, 用作 span 的字段
+    fn with<T>(
+        &self,
+        op: &'static str,
+        f: impl FnOnce(&Conn) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let _span = debug_span!("db", kind = "storage", op).entered();
+        let _timer = METRICS
+            .db_duration
+            .with_label_values(&["storage"])
+            .start_timer();
+        let conn = self
+            .pool
+            .get()
+            .map_err(|e| Error::WapperError(e.to_string()))?;
+        f(&conn)
+    }
+}
+
+#[cfg(feature = "sql")]
+impl Storage for SqlStorage {
+    fn add_user(&self, name: String, passwd: String) -> Result<(), Error> {
+        self.with("add_user", |c| user::add(c, name, passwd))
+    }
+
+    fn verify_user(&self, name: &str, passwd: &str) -> Result<QueryUser, Error> {
+        self.with("verify_user", |c| user::verification(c, name, passwd))
+    }
+
+    fn find_user(&self, user_id: i32) -> Result<QueryUser, Error> {
+        self.with("find_user", |c| user::find_with_id(c, user_id))
+    }
+
+    fn find_user_by_name(&self, name: &str) -> Result<QueryUser, Error> {
+        self.with("find_user_by_name", |c| user::find_with_username(c, name))
+    }
+
+    fn find_user_by_email(&self, email: &str) -> Result<QueryUser, Error> {
+        self.with("find_user_by_email", |c| user::find_with_email(c, email))
+    }
+
+    fn user_names(&self, user_ids: &[i32]) -> Result<Vec<(i32, String)>, Error> {
+        self.with("user_names", |c| user::names_of(c, user_ids))
+    }
+
+    fn change_passwd(&self, user_id: i32, old: &str, new: &str) -> Result<(), Error> {
+        self.with("change_passwd", |c| {
+            user::change_passwd(c, user_id, old, new)
+        })
+    }
+
+    fn set_passwd(&self, user_id: i32, new: &str) -> Result<(), Error> {
+        self.with("set_passwd", |c| user::set_passwd(c, user_id, new))
+    }
+
+    fn set_contacts_only(&self, user_id: i32, only: bool) -> Result<(), Error> {
+        self.with("set_contacts_only", |c| {
+            user::set_contacts_only(c, user_id, only)
+        })
+    }
+
+    fn profile(&self, user_id: i32) -> Result<QueryProfile, Error> {
+        self.with("profile", |c| user::profile(c, user_id))
+    }
+
+    fn update_profile(&self, user_id: i32, changes: &ProfileChangeset) -> Result<(), Error> {
+        self.with("update_profile", |c| {
+            user::update_profile(c, user_id, changes)
+        })
+    }
+
+    fn set_role(&self, user_id: i32, role: Role) -> Result<(), Error> {
+        self.with("set_role", |c| user::set_role(c, user_id, role))
+    }
+
+    fn users(&self, limit: i64, offset: i64) -> Result<Vec<UserSummary>, Error> {
+        self.with("users", |c| user::list(c, limit, offset))
+    }
+
+    fn deactivate(&self, user_id: i32) -> Result<(), Error> {
+        self.with("deactivate", |c| account::deactivate(c, user_id))
+    }
+
+    fn delete_account(&self, user_id: i32, restore_days: i64) -> Result<NaiveDateTime, Error> {
+        self.with("delete_account", |c| {
+            account::delete(c, user_id, restore_days)
+        })
+    }
+
+    fn find_restorable(&self, name: &str, passwd: &str) -> Result<QueryUser, Error> {
+        self.with("find_restorable", |c| {
+            account::find_restorable(c, name, passwd)
+        })
+    }
+
+    fn restore(&self, user_id: i32) -> Result<(), Error> {
+        self.with("restore", |c| account::restore(c, user_id))
+    }
+
+    fn purge_expired(&self, policy: MessagePolicy) -> Result<usize, Error> {
+        self.with("purge_expired", |c| account::purge_expired(c, policy))
+    }
+
+    fn join_room(&self, user_id: i32, name: &str) -> Result<(), Error> {
+        self.with("join_room", |c| room::join(c, user_id, name))
+    }
+
+    fn leave_room(&self, user_id: i32, name: &str) -> Result<(), Error> {
+        self.with("leave_room", |c| room::leave(c, user_id, name))
+    }
+
+    fn room_role(&self, user_id: i32, name: &str) -> Result<Option<RoomRole>, Error> {
+        self.with("room_role", |c| room::role_of(c, user_id, name))
+    }
+
+    fn set_room_role(&self, user_id: i32, name: &str, role: RoomRole) -> Result<(), Error> {
+        self.with("set_room_role", |c| room::set_role(c, user_id, name, role))
+    }
+
+    fn room_members(&self) -> Result<Vec<(String, i64)>, Error> {
+        self.with("room_members", room::list)
+    }
+
+    fn add_block(&self, user_id: i32, other: i32) -> Result<(), Error> {
+        self.with("add_block", |c| block::add(c, user_id, other))
+    }
+
+    fn remove_block(&self, user_id: i32, other: i32) -> Result<(), Error> {
+        self.with("remove_block", |c| block::remove(c, user_id, other))
+    }
+
+    fn blocks(&self, user_id: i32) -> Result<Vec<QueryBlock>, Error> {
+        self.with("blocks", |c| block::list(c, user_id))
+    }
+
+    fn is_blocked(&self, user_id: i32, other: i32) -> Result<bool, Error> {
+        self.with("is_blocked", |c| block::is_blocked(c, user_id, other))
+    }
+
+    fn contact_request(
+        &self,
+        from: i32,
+        to: i32,
+        message: Option<&str>,
+    ) -> Result<QueryContactRequest, Error> {
+        self.with("contact_request", |c| {
+            contact::request(c, from, to, message)
+        })
+    }
+
+    fn find_contact_request(&self, request_id: i64) -> Result<QueryContactRequest, Error> {
+        self.with("find_contact_request", |c| {
+            contact::find_request(c, request_id)
+        })
+    }
+
+    fn pending_contact_requests(&self, user_id: i32) -> Result<Vec<QueryContactRequest>, Error> {
+        self.with("pending_contact_requests", |c| {
+            contact::pending_for(c, user_id)
+        })
+    }
+
+    fn answer_contact_request(&self, request_id: i64, state: Tiny) -> Result<(), Error> {
+        self.with("answer_contact_request", |c| {
+            contact::answer(c, request_id, state)
+        })
+    }
+
+    fn remove_contact(&self, user_id: i32, other: i32) -> Result<(), Error> {
+        self.with("remove_contact", |c| contact::remove(c, user_id, other))
+    }
+
+    fn contacts(&self, user_id: i32) -> Result<Vec<i32>, Error> {
+        self.with("contacts", |c| contact::list(c, user_id))
+    }
+
+    fn are_contacts(&self, user_id: i32, other: i32) -> Result<bool, Error> {
+        self.with("are_contacts", |c| contact::are_contacts(c, user_id, other))
+    }
+
+    fn may_message(&self, from: i32, to: i32) -> Result<bool, Error> {
+        self.with("may_message", |c| contact::may_message(c, from, to))
+    }
+
+    fn add_sanction(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+        expire: Option<NaiveDateTime>,
+    ) -> Result<(), Error> {
+        self.with("add_sanction", |c| {
+            moderation::add(c, room, user_id, kind, expire)
+        })
+    }
+
+    fn remove_sanction(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+    ) -> Result<(), Error> {
+        self.with("remove_sanction", |c| {
+            moderation::remove(c, room, user_id, kind)
+        })
+    }
+
+    fn is_sanctioned(
+        &self,
+        room: Option<&str>,
+        user_id: i32,
+        kind: SanctionKind,
+    ) -> Result<bool, Error> {
+        self.with("is_sanctioned", |c| {
+            moderation::is_active(c, room, user_id, kind)
+        })
+    }
+
+    fn audit(
+        &self,
+        operator: i32,
+        action: &str,
+        target: i32,
+        room: Option<&str>,
+        detail: Option<&str>,
+    ) -> Result<(), Error> {
+        self.with("audit", |c| {
+            moderation::audit(c, operator, action, target, room, detail)
+        })
+    }
+
+    fn audit_logs(&self, limit: i64, offset: i64) -> Result<Vec<QueryAuditLog>, Error> {
+        self.with("audit_logs", |c| moderation::audit_logs(c, limit, offset))
+    }
+
+    fn add_room_message(&self, from: i32, name: &str, text: &str) -> Result<QueryMessage, Error> {
+        self.with("add_room_message", |c| {
+            message::add_room_message(c, from, name, text)
+        })
+    }
+
+    fn add_p2p_message(&self, from: i32, to: i32, text: &str) -> Result<QueryMessage, Error> {
+        self.with("add_p2p_message", |c| {
+            message::add_p2p_message(c, from, to, text)
+        })
+    }
+
+    fn add_broadcast_message(&self, from: i32, text: &str) -> Result<QueryMessage, Error> {
+        self.with("add_broadcast_message", |c| {
+            message::add_broadcast_message(c, from, text)
+        })
+    }
+
+    fn flag_message(&self, message_id: i64, filter: &str, reason: &str) -> Result<(), Error> {
+        self.with("flag_message", |c| {
+            message::flag(c, message_id, filter, reason)
+        })
+    }
+
+    fn pending_broadcasts(&self, user_id: i32, max: i64) -> Result<Vec<QueryMessage>, Error> {
+        self.with("pending_broadcasts", |c| {
+            message::pending_broadcasts(c, user_id, max)
+        })
+    }
+
+    fn mark_broadcast_read(&self, user_ids: &[i32], message_id: i64) -> Result<(), Error> {
+        self.with("mark_broadcast_read", |c| {
+            user::mark_broadcast_read(c, user_ids, message_id)
+        })
+    }
+}
+
+// 各后端共用的测试, 每个函数需要一个空的存储
+#[cfg(test)]
+pub(super) mod suite {
+    use super::*;
+    use crate::db::contact::{ACCEPTED, DECLINED};
+    use crate::db::moderation::now;
+    use chrono::Duration;
+
+    fn add(s: &dyn Storage, name: &str) -> i32 {
+        s.add_user(name.to_owned(), "passwd".to_owned()).unwrap();
+        s.find_user_by_name(name).unwrap().user_id
+    }
+
+    pub fn users(s: &dyn Storage) {
+        let a = add(s, "alice");
+        assert!(matches!(
+            s.add_user("alice".to_owned(), "x".to_owned()),
+            Err(Error::DuplicateData(_))
+        ));
+        assert_eq!(s.verify_user("alice", "passwd").unwrap().user_id, a);
+        assert!(matches!(s.verify_user("alice", "x"), Err(Error::NotFound)));
+        assert!(matches!(
+            s.change_passwd(a, "x", "new"),
+            Err(Error::NotFound)
+        ));
+        s.change_passwd(a, "passwd", "new").unwrap();
+        s.verify_user("alice", "new").unwrap();
+        s.set_passwd(a, "passwd").unwrap();
+        s.verify_user("alice", "passwd").unwrap();
+
+        let b = add(s, "bob");
+        let email = ProfileChangeset {
+            email: Some(Some("a@example.com".to_owned())),
+            ..ProfileChangeset::default()
+        };
+        s.update_profile(a, &email).unwrap();
+        assert!(matches!(
+            s.update_profile(b, &email),
+            Err(Error::DuplicateData(_))
+        ));
+        assert_eq!(s.find_user_by_email("a@example.com").unwrap().user_id, a);
+        assert_eq!(
+            s.profile(a).unwrap().email.as_deref(),
+            Some("a@example.com")
+        );
+
+        s.set_role(b, Role::Moderator).unwrap();
+        assert_eq!(s.find_user(b).unwrap().role(), Role::Moderator);
+        // 角色不变时也算成功
+        s.set_role(b, Role::Moderator).unwrap();
+        assert!(matches!(s.set_role(-1, Role::Admin), Err(Error::NotFound)));
+        let names: Vec<i32> = s.users(10, 0).unwrap().iter().map(|u| u.user_id).collect();
+        assert_eq!(names, vec![a, b]);
+        assert_eq!(s.users(10, 1).unwrap().len(), 1);
+    }
+
+    pub fn rooms(s: &dyn Storage) {
+        let (a, b) = (add(s, "alice"), add(s, "bob"));
+        s.join_room(a, "rust").unwrap();
+        s.join_room(b, "rust").unwrap();
+        s.join_room(b, "go").unwrap();
+        assert_eq!(s.room_role(a, "rust").unwrap(), Some(RoomRole::Member));
+        assert_eq!(s.room_role(a, "go").unwrap(), None);
+        assert_eq!(
+            s.room_members().unwrap(),
+            vec![("go".to_owned(), 1), ("rust".to_owned(), 2)]
+        );
+
+        s.set_room_role(a, "rust", RoomRole::Manager).unwrap();
+        s.set_room_role(a, "rust", RoomRole::Manager).unwrap();
+        assert_eq!(s.room_role(a, "rust").unwrap(), Some(RoomRole::Manager));
+        assert!(matches!(
+            s.set_room_role(a, "go", RoomRole::Manager),
+            Err(Error::NotFound)
+        ));
+        s.leave_room(a, "rust").unwrap();
+        assert_eq!(s.room_role(a, "rust").unwrap(), None);
+    }
+
+    pub fn sanctions(s: &dyn Storage) {
+        let a = add(s, "alice");
+        let mute = SanctionKind::Mute;
+        s.add_sanction(Some("rust"), a, mute, None).unwrap();
+        assert!(s.is_sanctioned(Some("rust"), a, mute).unwrap());
+        assert!(!s.is_sanctioned(Some("go"), a, mute).unwrap());
+        assert!(!s.is_sanctioned(None, a, mute).unwrap());
+        assert!(!s.is_sanctioned(Some("rust"), a, SanctionKind::Ban).unwrap());
+
+        // 全站的处罚在每个房间都生效, 过期的不算
+        s.add_sanction(None, a, SanctionKind::Ban, None).unwrap();
+        assert!(s.is_sanctioned(Some("go"), a, SanctionKind::Ban).unwrap());
+        let past = now() - Duration::minutes(1);
+        s.add_sanction(None, a, SanctionKind::Ban, Some(past))
+            .unwrap();
+        assert!(!s.is_sanctioned(None, a, SanctionKind::Ban).unwrap());
+
+        s.remove_sanction(Some("rust"), a, mute).unwrap();
+        assert!(!s.is_sanctioned(Some("rust"), a, mute).unwrap());
+    }
+
+    pub fn audit_logs(s: &dyn Storage) {
+        let (a, b) = (add(s, "alice"), add(s, "bob"));
+        s.audit(a, "mute", b, Some("rust"), None).unwrap();
+        s.audit(a, "ban", b, None, Some("spam")).unwrap();
+        let logs = s.audit_logs(10, 0).unwrap();
+        let actions: Vec<&str> = logs.iter().map(|l| l.action.as_str()).collect();
+        assert_eq!(actions, vec!["ban", "mute"]);
+        assert_eq!(logs[0].detail.as_deref(), Some("spam"));
+        assert_eq!(s.audit_logs(1, 1).unwrap()[0].action, "mute");
+    }
+
+    pub fn blocks(s: &dyn Storage) {
+        let (a, b, c) = (add(s, "alice"), add(s, "bob"), add(s, "carol"));
+        s.add_block(a, b).unwrap();
+        s.add_block(a, c).unwrap();
+        assert!(matches!(s.add_block(a, b), Err(Error::DuplicateData(_))));
+        assert!(s.is_blocked(a, b).unwrap());
+        assert!(!s.is_blocked(b, a).unwrap());
+        let blocked: Vec<i32> = s.blocks(a).unwrap().iter().map(|b| b.blocked).collect();
+        assert_eq!(blocked.len(), 2);
+        assert!(blocked.contains(&b) && blocked.contains(&c));
+
+        s.remove_block(a, b).unwrap();
+        assert!(!s.is_blocked(a, b).unwrap());
+        assert!(matches!(s.remove_block(a, b), Err(Error::NotFound)));
+    }
+
+    pub fn contacts(s: &dyn Storage) {
+        let (a, b, c) = (add(s, "alice"), add(s, "bob"), add(s, "carol"));
+        let req = s.contact_request(a, b, Some("hi")).unwrap();
+        assert_eq!(s.find_contact_request(req.request_id).unwrap().to_user, b);
+        assert_eq!(s.pending_contact_requests(b).unwrap().len(), 1);
+        assert!(s.pending_contact_requests(a).unwrap().is_empty());
+
+        s.answer_contact_request(req.request_id, ACCEPTED).unwrap();
+        assert!(matches!(
+            s.answer_contact_request(req.request_id, DECLINED),
+            Err(Error::NotFound)
+        ));
+        assert!(s.are_contacts(a, b).unwrap() && s.are_contacts(b, a).unwrap());
+        assert_eq!(s.contacts(a).unwrap(), vec![b]);
+        assert!(s.pending_contact_requests(b).unwrap().is_empty());
+
+        // 拒绝后可以再次申请
+        let req = s.contact_request(c, a, None).unwrap();
+        s.answer_contact_request(req.request_id, DECLINED).unwrap();
+        assert!(!s.are_contacts(a, c).unwrap());
+        let again = s.contact_request(c, a, None).unwrap();
+        assert_eq!(again.request_id, req.request_id);
+        assert_eq!(s.pending_contact_requests(a).unwrap().len(), 1);
+
+        s.remove_contact(b, a).unwrap();
+        assert!(!s.are_contacts(a, b).unwrap() && !s.are_contacts(b, a).unwrap());
+        assert!(matches!(s.remove_contact(a, b), Err(Error::NotFound)));
+    }
+
+    pub fn contacts_only(s: &dyn Storage) {
+        let (a, b, c) = (add(s, "alice"), add(s, "bob"), add(s, "carol"));
+        assert!(s.may_message(b, a).unwrap());
+        s.set_contacts_only(a, true).unwrap();
+        assert!(!s.may_message(b, a).unwrap());
+        // 可以回复对方发起的会话
+        s.add_p2p_message(a, b, "hi").unwrap();
+        assert!(s.may_message(b, a).unwrap());
+        assert!(!s.may_message(c, a).unwrap());
+        let req = s.contact_request(c, a, None).unwrap();
+        s.answer_contact_request(req.request_id, ACCEPTED).unwrap();
+        assert!(s.may_message(c, a).unwrap());
+        assert!(matches!(s.may_message(a, -1), Err(Error::NotFound)));
+    }
+
+    pub fn broadcasts(s: &dyn Storage) {
+        let (a, b) = (add(s, "alice"), add(s, "bob"));
+        let first = s.add_broadcast_message(a, "one").unwrap();
+        let second = s.add_broadcast_message(a, "two").unwrap();
+        s.add_room_message(a, "rust", "not a broadcast").unwrap();
+        let pending = s.pending_broadcasts(b, 10).unwrap();
+        let ids: Vec<i64> = pending.iter().map(|m| m.message_id).collect();
+        assert_eq!(ids, vec![first.message_id, second.message_id]);
+        assert_eq!(s.pending_broadcasts(b, 1).unwrap()[0].content, "two");
+
+        s.mark_broadcast_read(&[b], first.message_id).unwrap();
+        assert_eq!(s.pending_broadcasts(b, 10).unwrap().len(), 1);
+        s.mark_broadcast_read(&[b], second.message_id).unwrap();
+        assert!(s.pending_broadcasts(b, 10).unwrap().is_empty());
+        s.flag_message(second.message_id, "links", "link").unwrap();
+    }
+
+    pub fn accounts(s: &dyn Storage) {
+        let a = add(s, "alice");
+        s.deactivate(a).unwrap();
+        assert!(matches!(s.deactivate(a), Err(Error::NotFound)));
+        assert!(matches!(s.find_user(a), Err(Error::NotFound)));
+        assert!(matches!(
+            s.verify_user("alice", "passwd"),
+            Err(Error::NotFound)
+        ));
+        assert!(s.users(10, 0).unwrap()[0].delete_time.is_some());
+        // 停用的账号不会被清除
+        assert_eq!(s.purge_expired(MessagePolicy::Erase).unwrap(), 0);
+
+        let u = s.find_restorable("alice", "passwd").unwrap();
+        assert!(matches!(
+            s.find_restorable("alice", "x"),
+            Err(Error::NotFound)
+        ));
+        s.restore(u.user_id).unwrap();
+        s.find_user(a).unwrap();
+        assert!(matches!(
+            s.find_restorable("alice", "passwd"),
+            Err(Error::NotFound)
+        ));
+
+        let before = s.delete_account(a, 30).unwrap();
+        assert!(before > now() + Duration::days(29));
+        assert!(matches!(s.find_user(a), Err(Error::NotFound)));
+        assert_eq!(s.purge_expired(MessagePolicy::Keep).unwrap(), 0);
+        s.find_restorable("alice", "passwd").unwrap();
+    }
+
+    pub fn purge(s: &dyn Storage) {
+        let (a, b) = (add(s, "alice"), add(s, "bob"));
+        s.join_room(a, "rust").unwrap();
+        s.add_block(b, a).unwrap();
+        let req = s.contact_request(a, b, None).unwrap();
+        s.answer_contact_request(req.request_id, ACCEPTED).unwrap();
+        s.add_room_message(a, "rust", "secret").unwrap();
+
+        // 恢复期为 0 天, 立即可以清除
+        s.delete_account(a, 0).unwrap();
+        assert_eq!(s.purge_expired(MessagePolicy::Redact).unwrap(), 1);
+        assert_eq!(s.purge_expired(MessagePolicy::Redact).unwrap(), 0);
+        assert!(matches!(
+            s.find_restorable("alice", "passwd"),
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(s.restore(a), Err(Error::NotFound)));
+        assert_eq!(s.room_role(a, "rust").unwrap(), None);
+        assert!(!s.is_blocked(b, a).unwrap());
+        assert!(s.contacts(b).unwrap().is_empty());
+        let names: Vec<i32> = s.users(10, 0).unwrap().iter().map(|u| u.user_id).collect();
+        assert_eq!(names, vec![b]);
+        // 用户名空出来可以重新注册
+        add(s, "alice");
+    }
+}
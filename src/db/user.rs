@@ -1,20 +1,120 @@
+#[cfg(feature = "sql")]
 use super::error::{deal_insert_result, deal_query_result, deal_update_result, Error};
-use super::establish_connection;
 use super::schema::users;
+#[cfg(feature = "sql")]
+use super::Conn;
+use super::Tiny;
 use chrono::NaiveDateTime;
+#[cfg(feature = "sql")]
 use diesel::prelude::*;
-use diesel::result::DatabaseErrorKind::UniqueViolation;
+use serde::Serialize;
+use serde_repr::*;
 
-#[derive(Queryable)]
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, PartialOrd, Debug)]
+#[repr(i8)]
+pub enum Role {
+    Member = 0,
+    Moderator = 1,
+    Admin = 2,
+}
+
+impl From<Tiny> for Role {
+    fn from(v: Tiny) -> Role {
+        match v {
+            2 => Role::Admin,
+            1 => Role::Moderator,
+            _ => Role::Member,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
 pub struct QueryUser {
     pub user_id: i32,
     pub user_name: String,
     pub passwd: String,
-    create_time: NaiveDateTime,
-    updata_time: Option<NaiveDateTime>,
-    delete_time: Option<NaiveDateTime>,
+    pub create_time: NaiveDateTime,
+    pub(super) role: Tiny,
+    pub last_broadcast_id: i64,
+    pub contacts_only: bool,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub bio: Option<String>,
+}
+
+// QueryUser 对应的列, 注销相关的列只用于过滤
+#[cfg(feature = "sql")]
+pub(super) const COLUMNS: (
+    users::user_id,
+    users::user_name,
+    users::passwd,
+    users::create_time,
+    users::role,
+    users::last_broadcast_id,
+    users::contacts_only,
+    users::display_name,
+    users::avatar,
+    users::email,
+    users::phone,
+    users::bio,
+) = (
+    users::user_id,
+    users::user_name,
+    users::passwd,
+    users::create_time,
+    users::role,
+    users::last_broadcast_id,
+    users::contacts_only,
+    users::display_name,
+    users::avatar,
+    users::email,
+    users::phone,
+    users::bio,
+);
+
+impl QueryUser {
+    pub fn role(&self) -> Role {
+        Role::from(self.role)
+    }
+}
+
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryProfile {
+    pub user_id: i32,
+    pub user_name: String,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub bio: Option<String>,
 }
 
+// 管理后台的用户列表, 包括已停用/注销但未清除的账号
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSummary {
+    pub user_id: i32,
+    pub user_name: String,
+    pub role: Role,
+    pub create_time: NaiveDateTime,
+    pub delete_time: Option<NaiveDateTime>,
+}
+
+// 外层 None 不修改, Some(None) 清空
+#[derive(AsChangeset, Default)]
+#[table_name = "users"]
+pub struct ProfileChangeset {
+    pub display_name: Option<Option<String>>,
+    pub avatar: Option<Option<String>>,
+    pub email: Option<Option<String>>,
+    pub phone: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+}
+
+#[cfg(feature = "sql")]
 #[derive(Insertable)]
 #[table_name = "users"]
 struct InsertableUser {
@@ -22,60 +122,212 @@ struct InsertableUser {
     passwd: String,
 }
 
-pub fn add(u_name: String, pd: String) -> Result<(), Error> {
+#[cfg(feature = "sql")]
+pub fn add(conn: &Conn, u_name: String, pd: String) -> Result<(), Error> {
     use super::schema::users::dsl::*;
-    let connection = establish_connection();
     let new_user = InsertableUser {
         user_name: u_name,
         passwd: pd,
     };
-    let r = diesel::insert_into(users)
-        .values(&new_user)
-        .execute(&connection);
+    let r = diesel::insert_into(users).values(&new_user).execute(conn);
     deal_insert_result(r)
 }
 
-pub fn verification(u_name: &str, pd: &str) -> Result<QueryUser, Error> {
+#[cfg(feature = "sql")]
+pub fn verification(conn: &Conn, u_name: &str, pd: &str) -> Result<QueryUser, Error> {
     use super::schema::users::dsl::*;
-    let connection = establish_connection();
     let r: QueryResult<QueryUser> = users
+        .select(COLUMNS)
         .filter(user_name.eq(u_name))
         .filter(passwd.eq(pd))
-        .first(&connection);
+        .filter(delete_time.is_null())
+        .first(conn);
+    deal_query_result(r)
+}
+
+#[cfg(feature = "sql")]
+pub fn find_with_username(conn: &Conn, u_name: &str) -> Result<QueryUser, Error> {
+    use super::schema::users::dsl::*;
+    let r: QueryResult<QueryUser> = users
+        .select(COLUMNS)
+        .filter(user_name.eq(u_name))
+        .filter(delete_time.is_null())
+        .first(conn);
+    deal_query_result(r)
+}
+
+// 已停用/注销的用户视为不存在
+#[cfg(feature = "sql")]
+pub fn find_with_id(conn: &Conn, u_id: i32) -> Result<QueryUser, Error> {
+    use super::schema::users::dsl::*;
+    let r: QueryResult<QueryUser> = users
+        .select(COLUMNS)
+        .filter(user_id.eq(u_id))
+        .filter(delete_time.is_null())
+        .first(conn);
     deal_query_result(r)
 }
 
-fn find_with_username(u_name: &str) -> Result<QueryUser, Error> {
+// 旧密码不匹配时返回 NotFound
+#[cfg(feature = "sql")]
+pub fn change_passwd(conn: &Conn, u_id: i32, old: &str, new: &str) -> Result<(), Error> {
+    use super::schema::users::dsl::*;
+    let r = diesel::update(
+        users
+            .find(u_id)
+            .filter(passwd.eq(old))
+            .filter(delete_time.is_null()),
+    )
+    .set(passwd.eq(new))
+    .execute(conn);
+    match r {
+        // 新旧密码相同时影响行数为 0, 由调用方提前拒绝
+        Ok(0) => Err(Error::NotFound),
+        Ok(_) => Ok(()),
+        Err(e) => deal_update_result(Err(e)),
+    }
+}
+
+#[cfg(feature = "sql")]
+pub fn set_passwd(conn: &Conn, u_id: i32, new: &str) -> Result<(), Error> {
+    use super::schema::users::dsl::*;
+    let r = diesel::update(users.find(u_id).filter(delete_time.is_null()))
+        .set(passwd.eq(new))
+        .execute(conn);
+    match r {
+        Ok(_) => Ok(()),
+        Err(e) => deal_update_result(Err(e)),
+    }
+}
+
+// 先确认用户存在; MySQL 在角色不变时影响行数为 0, 不能据此判断
+#[cfg(feature = "sql")]
+pub fn set_role(conn: &Conn, u_id: i32, new_role: Role) -> Result<(), Error> {
     use super::schema::users::dsl::*;
-    let connection = establish_connection();
-    let r: QueryResult<QueryUser> = users.filter(user_name.eq(u_name)).first(&connection);
+    find_with_id(conn, u_id)?;
+    let r = diesel::update(users.find(u_id).filter(delete_time.is_null()))
+        .set(role.eq(new_role as Tiny))
+        .execute(conn);
+    deal_query_result(r).map(|_| ())
+}
+
+#[cfg(feature = "sql")]
+pub fn find_with_email(conn: &Conn, mail: &str) -> Result<QueryUser, Error> {
+    use super::schema::users::dsl::*;
+    let r: QueryResult<QueryUser> = users
+        .select(COLUMNS)
+        .filter(email.eq(mail))
+        .filter(delete_time.is_null())
+        .first(conn);
     deal_query_result(r)
 }
 
-fn find_with_id(u_id: i32) -> Result<QueryUser, Error> {
+// 记录广播已送达, 只会往前推进
+#[cfg(feature = "sql")]
+pub fn mark_broadcast_read(conn: &Conn, u_ids: &[i32], m_id: i64) -> Result<(), Error> {
+    use super::schema::users::dsl::*;
+    let r = diesel::update(
+        users
+            .filter(user_id.eq_any(u_ids))
+            .filter(last_broadcast_id.lt(m_id)),
+    )
+    .set(last_broadcast_id.eq(m_id))
+    .execute(conn);
+    deal_query_result(r).map(|_| ())
+}
+
+#[cfg(feature = "sql")]
+pub fn set_contacts_only(conn: &Conn, u_id: i32, only: bool) -> Result<(), Error> {
+    use super::schema::users::dsl::*;
+    let r = diesel::update(users.find(u_id))
+        .set(contacts_only.eq(only))
+        .execute(conn);
+    deal_query_result(r).map(|_| ())
+}
+
+// 用户名, 不存在或已停用的用户不返回
+#[cfg(feature = "sql")]
+pub fn names_of(conn: &Conn, u_ids: &[i32]) -> Result<Vec<(i32, String)>, Error> {
     use super::schema::users::dsl::*;
+    let r: QueryResult<Vec<(i32, String)>> = users
+        .filter(user_id.eq_any(u_ids))
+        .filter(delete_time.is_null())
+        .select((user_id, user_name))
+        .load(conn);
+    deal_query_result(r)
+}
 
-    let connection = establish_connection();
-    let r: QueryResult<QueryUser> = users.filter(user_id.eq(u_id)).first(&connection);
+#[cfg(feature = "sql")]
+pub fn profile(conn: &Conn, u_id: i32) -> Result<QueryProfile, Error> {
+    use super::schema::users::dsl::*;
+    let r: QueryResult<QueryProfile> = users
+        .find(u_id)
+        .filter(delete_time.is_null())
+        .select((user_id, user_name, display_name, avatar, email, phone, bio))
+        .first(conn);
     deal_query_result(r)
 }
 
-pub fn change_passwd(u_name: String, pd: String) -> Result<(), Error> {
+// 邮箱和手机号唯一, 冲突时返回 DuplicateData(字段名)
+#[cfg(feature = "sql")]
+pub fn update_profile(conn: &Conn, u_id: i32, changes: &ProfileChangeset) -> Result<(), Error> {
     use super::schema::users::dsl::*;
-    let u = find_with_username(u_name.as_str());
-    match u {
-        Ok(_u) => {
-            // TODO: 是否给提示？
-            if pd == _u.passwd {
-                Ok(())
-            } else {
-                let connection = establish_connection();
-                let r = diesel::update(users.find(_u.user_id))
-                    .set(passwd.eq(pd))
-                    .execute(&connection);
-                deal_update_result(r)
-            }
+    if let Some(Some(ref v)) = changes.email {
+        let r: QueryResult<i64> = users
+            .filter(email.eq(v))
+            .filter(user_id.ne(u_id))
+            .count()
+            .get_result(conn);
+        if deal_query_result(r)? > 0 {
+            return Err(Error::DuplicateData("email".to_owned()));
         }
-        Err(e) => Err(e),
     }
+    if let Some(Some(ref v)) = changes.phone {
+        let r: QueryResult<i64> = users
+            .filter(phone.eq(v))
+            .filter(user_id.ne(u_id))
+            .count()
+            .get_result(conn);
+        if deal_query_result(r)? > 0 {
+            return Err(Error::DuplicateData("phone".to_owned()));
+        }
+    }
+    let r = diesel::update(users.find(u_id)).set(changes).execute(conn);
+    match r {
+        // 内容未变化时影响行数为 0
+        Ok(_) => Ok(()),
+        Err(e) => deal_update_result(Err(e)),
+    }
+}
+
+#[cfg(feature = "sql")]
+#[derive(Queryable)]
+struct SummaryRow {
+    user_id: i32,
+    user_name: String,
+    role: Tiny,
+    create_time: NaiveDateTime,
+    delete_time: Option<NaiveDateTime>,
+}
+
+#[cfg(feature = "sql")]
+pub fn list(conn: &Conn, limit: i64, offset: i64) -> Result<Vec<UserSummary>, Error> {
+    use super::schema::users::dsl::*;
+    let r: QueryResult<Vec<SummaryRow>> = users
+        .filter(purged.eq(false))
+        .select((user_id, user_name, role, create_time, delete_time))
+        .order(user_id)
+        .limit(limit)
+        .offset(offset)
+        .load(conn);
+    Ok(deal_query_result(r)?
+        .into_iter()
+        .map(|u| UserSummary {
+            user_id: u.user_id,
+            user_name: u.user_name,
+            role: Role::from(u.role),
+            create_time: u.create_time,
+            delete_time: u.delete_time,
+        })
+        .collect())
 }
@@ -0,0 +1,106 @@
+use crate::chat::route::Draining;
+use crate::chat::server::{ChatServer, Ping};
+use crate::db::error::Error;
+#[cfg(feature = "sql")]
+use crate::db::migrate;
+use crate::db::{RedisPool, Repository};
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use r2d2_redis::redis;
+use serde_json::{json, Map};
+use std::future::Future;
+use std::time::Duration;
+
+// 探针要在编排系统的超时之前返回, 连接池取连接默认要等 30 秒
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// GET /healthz: 进程在运行且 ChatServer 能处理消息, 失败时应重启进程
+pub async fn healthz(srv: web::Data<Addr<ChatServer>>) -> HttpResponse {
+    match srv.send(Ping).timeout(CHECK_TIMEOUT).await {
+        Ok(_) => HttpResponse::Ok().json(json!({ "status": "ok" })),
+        Err(e) => HttpResponse::ServiceUnavailable()
+            .json(json!({ "status": "fail", "chat_server": e.to_string() })),
+    }
+}
+
+// GET /readyz: 数据库和 redis 都能取到连接, 迁移都已执行, 且没有在关闭;
+// 失败时不应再分配流量, 每一项的结果都列在响应中
+pub async fn readyz(
+    repo: web::Data<Repository>,
+    redis: web::Data<RedisPool>,
+    draining: web::Data<Draining>,
+) -> HttpResponse {
+    let mut checks = Map::new();
+    let mut ready = !draining.is_set();
+    if !ready {
+        checks.insert("server".to_owned(), json!("shutting down"));
+    }
+
+    let database = database(&repo).await;
+    let redis = redis.get_ref().clone();
+    let redis = with_timeout(async move {
+        web::block(move || {
+            let mut conn = redis.get().map_err(|e| Error::WapperError(e.to_string()))?;
+            redis::cmd("PING")
+                .query::<String>(&mut *conn)
+                .map_err(|e| Error::WapperError(e.to_string()))
+        })
+        .await
+        .map_err(Error::from)
+    })
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string());
+
+    for (name, result) in [("database", database), ("redis", redis)] {
+        let value = match result {
+            Ok(()) => json!("ok"),
+            Err(e) => {
+                ready = false;
+                json!(e)
+            }
+        };
+        checks.insert(name.to_owned(), value);
+    }
+
+    let body = json!({ "status": if ready { "ok" } else { "fail" }, "checks": checks });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+// 能取到数据库连接且迁移都已执行
+#[cfg(feature = "sql")]
+async fn database(repo: &Repository) -> Result<(), String> {
+    match with_timeout(repo.run(migrate::status)).await {
+        Ok(status) => {
+            let pending: Vec<&str> = status
+                .iter()
+                .filter(|(_, applied)| !applied)
+                .map(|(m, _)| m.name)
+                .collect();
+            if pending.is_empty() {
+                Ok(())
+            } else {
+                Err(format!("pending migrations: {}", pending.join(", ")))
+            }
+        }
+        // 不连接数据库时没有需要检查的
+        Err(Error::Unsupported) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// 不带数据库编译时只有内存存储, 没有需要检查的
+#[cfg(not(feature = "sql"))]
+async fn database(_: &Repository) -> Result<(), String> {
+    Ok(())
+}
+
+async fn with_timeout<T>(f: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    actix_rt::time::timeout(CHECK_TIMEOUT, f)
+        .await
+        .unwrap_or_else(|_| Err(Error::WapperError("timed out".to_owned())))
+}
@@ -0,0 +1,70 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// 发送重置密码链接等邮件, 实现它以接入真正的发送方式 (SMTP, 邮件服务 api 等)
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), String>;
+}
+
+// 以 debug 级别记录收件人和主题, 用于本地测试; 正文含重置令牌, 不记录
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, mail: &Mail) -> Result<(), String> {
+        debug!(to = %mail.to, subject = %mail.subject, "mail not sent");
+        Ok(())
+    }
+}
+
+// 追加写入文件, 用于本地测试
+pub struct FileMailer {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn new(path: PathBuf) -> FileMailer {
+        FileMailer {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &Mail) -> Result<(), String> {
+        let _guard = self.lock.lock().map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| e.to_string())?;
+        writeln!(
+            file,
+            "To: {}\nSubject: {}\n\n{}\n",
+            mail.to, mail.subject, mail.body
+        )
+        .map_err(|e| e.to_string())
+    }
+}
+
+// `log` 或 `file:<path>`, 由 `config::Config::validate` 检查
+pub fn is_valid(value: &str) -> bool {
+    value == "log" || value.strip_prefix("file:").is_some_and(|p| !p.is_empty())
+}
+
+pub fn from_config(value: &str) -> Arc<dyn Mailer> {
+    match value.strip_prefix("file:") {
+        Some(path) => Arc::new(FileMailer::new(PathBuf::from(path))),
+        None => Arc::new(LogMailer),
+    }
+}
@@ -4,47 +4,289 @@ extern crate dotenv;
 extern crate r2d2_redis;
 
 use actix::*;
-use actix_web::{http, middleware, middleware::errhandlers::ErrorHandlers, web, App, HttpServer};
+use actix_http::HttpService;
+use actix_service::map_config;
+use actix_web::dev::{AppConfig, Server};
+use actix_web::{http, middleware::errhandlers::ErrorHandlers, web, App};
 use api::route::write_400;
-use chat::{route, server};
-use diesel::{r2d2::ConnectionManager, MysqlConnection};
-use std::env;
+use chat::filter::FilterChain;
+use chat::limit::RateLimiter;
+use chat::route::{self, Draining};
+use chat::server;
+use config::Config;
+use db::memory::MemoryStorage;
+#[cfg(feature = "sql")]
+use db::migrate;
+use db::search::SearchEngine;
+#[cfg(feature = "sql")]
+use db::search::SqlFulltext;
+#[cfg(feature = "sql")]
+use db::storage::SqlStorage;
+use db::storage::StorageKind;
+use db::Repository;
+#[cfg(feature = "sql")]
+use diesel::r2d2::ConnectionManager;
 use r2d2_redis::{r2d2 as redis_r2d2, RedisConnectionManager};
+use std::fmt::Display;
+use std::io;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
 
 mod api;
 mod chat;
+mod cli;
+mod config;
+mod cors;
 mod db;
+mod health;
+mod mail;
+mod metrics;
+mod telemetry;
+mod tls;
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    env::set_var("RUST_LOG", "actix_web=debug,actix_server=info");
-    env_logger::init();
     dotenv::dotenv().ok();
+    let matches = cli::app().get_matches();
+    let needs_redis = cli::needs_redis(&matches);
+    let config = Config::load(cli::innermost(&matches), needs_redis).unwrap_or_else(|e| {
+        eprintln!("invalid configuration:\n{}", e);
+        process::exit(2)
+    });
+    telemetry::init(&config);
 
-    let mysql_connspec = env::var("DATABASE_URL").expect("need set `DATABASE_URL` at .env");
-    let mysql_manager = ConnectionManager::<MysqlConnection>::new(mysql_connspec);
-    let mysql_pool = r2d2::Pool::builder()
-        .build(mysql_manager)
-        .expect("Fail to create pool.");
+    let r = match matches.subcommand() {
+        #[cfg(feature = "sql")]
+        ("migrate", Some(sub)) => cli::migrate(&config, sub),
+        #[cfg(feature = "sql")]
+        ("user", Some(sub)) => cli::user(&config, sub),
+        #[cfg(feature = "sql")]
+        ("room", Some(sub)) => cli::room(&config, sub),
+        #[cfg(not(feature = "sql"))]
+        ("migrate", Some(sub)) | ("user", Some(sub)) | ("room", Some(sub)) => {
+            cli::unsupported(&config, sub)
+        }
+        ("config", Some(sub)) => cli::config(&config, sub),
+        ("export", Some(_)) => cli::export(&config),
+        _ => return serve(config).await,
+    };
+    if let Err(e) = r {
+        exit_with(matches.subcommand_name().unwrap_or_default(), e);
+    }
+    Ok(())
+}
 
-    let redis_connspec = env::var("REDIS_URL").expect("need set `REDIS_URL at .env");
-    let redis_manager = RedisConnectionManager::new(redis_connspec).expect("Fail to create redis manager");
+async fn serve(config: Config) -> io::Result<()> {
+    let redis_connspec = config.redis.url.clone().unwrap_or_default();
+    let redis_manager = RedisConnectionManager::new(redis_connspec)
+        .unwrap_or_else(|e| exit_with("Fail to create redis manager", e));
+    // 不在启动时连接 redis, 连不上时由 `/readyz` 报告
     let redis_pool = redis_r2d2::Pool::builder()
-        .build(redis_manager)
-        .expect("Fail to create redis pool");
+        .max_size(config.redis.pool_size)
+        .build_unchecked(redis_manager);
+
+    let limiter = web::Data::new(RateLimiter::new(
+        config.rate_limit.clone(),
+        redis_pool.clone(),
+    ));
 
-    let srv = server::ChatServer::default().start();
+    let (repo, search): (Repository, Arc<dyn SearchEngine>) = match config.database.storage {
+        #[cfg(feature = "sql")]
+        StorageKind::Sql => {
+            let db_connspec = config.database.url.clone().unwrap_or_default();
+            let db_manager = ConnectionManager::<db::Conn>::new(db_connspec);
+            let db_pool = r2d2::Pool::builder()
+                .max_size(config.database.pool_size)
+                .build(db_manager)
+                .unwrap_or_else(|e| exit_with("Fail to create pool", e));
+            if config.database.run_migrations {
+                let conn = db_pool
+                    .get()
+                    .unwrap_or_else(|e| exit_with("Fail to get connection", e));
+                let done = migrate::run_pending(&conn)
+                    .unwrap_or_else(|e| exit_with("Fail to run migrations", e));
+                for m in done {
+                    info!(migration = %m.name, "applied migration");
+                }
+            }
+            let storage = Arc::new(SqlStorage::new(db_pool.clone()));
+            let repo = Repository::new(storage).with_pool(db_pool);
+            let search = Arc::new(SqlFulltext::new(repo.clone()));
+            (repo, search)
+        }
+        StorageKind::Memory => {
+            let storage = Arc::new(MemoryStorage::new());
+            (Repository::new(storage.clone()), storage)
+        }
+        // 配置校验已经拒绝
+        #[cfg(not(feature = "sql"))]
+        StorageKind::Sql => unreachable!("storage sql without a database feature"),
+    };
+    let mailer = mail::from_config(&config.mail.mailer);
+    let filters = FilterChain::from_config(config.filter.clone());
+    // 聊天服务在独立线程上路由消息, 它的存储调用在阻塞线程池上执行
+    let srv = {
+        let (repo, search) = (repo.clone(), search.clone());
+        server::ChatServer::start_in_arbiter(&Arbiter::new(), move |_| {
+            server::ChatServer::new(repo, search, filters)
+        })
+    };
 
-    HttpServer::new(move || {
+    // 每小时清除一次恢复期已过的注销账号
+    let accounts = web::Data::new(config.account);
+    let policy = accounts.messages;
+    let purge_repo = repo.clone();
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match purge_repo.store(move |s| s.purge_expired(policy)).await {
+                Ok(0) => (),
+                Ok(n) => info!(count = n, "purged deleted accounts"),
+                Err(e) => error!(error = ?e, "purge deleted accounts failed"),
+            }
+        }
+    });
+
+    let binds = config.server.bind.clone();
+    let tls_binds = config.tls.bind.clone();
+    let acceptor = if config.tls.enabled() {
+        let acceptor = tls::acceptor(&config.tls)
+            .unwrap_or_else(|e| exit_with("Fail to load tls certificate", e));
+        Some(acceptor)
+    } else {
+        None
+    };
+    let workers = config.server.workers;
+    let shutdown_timeout = config.server.shutdown_timeout;
+    let reconnect_after = config.websocket.reconnect_after;
+    let draining = web::Data::new(Draining::default());
+    let config = web::Data::new(config);
+    let chat_server = srv.clone();
+    let drain_flag = draining.clone();
+    let app = move || {
+        let features = config.features;
         App::new()
             .data(srv.clone())
-            .data(mysql_pool.clone())
+            .data(repo.clone())
             .data(redis_pool.clone())
-            .wrap(middleware::Logger::default())
+            .data(search.clone())
+            .data(mailer.clone())
+            .app_data(limiter.clone())
+            .app_data(accounts.clone())
+            .app_data(config.clone())
+            .app_data(draining.clone())
+            .app_data(web::JsonConfig::default().limit(config.server.json_limit))
+            .wrap_fn(metrics::track_http)
+            .wrap_fn(telemetry::trace_http)
             .wrap(ErrorHandlers::new().handler(http::StatusCode::BAD_REQUEST, write_400))
+            .configure(|cfg| api::route::config(cfg, &features))
             .service(web::resource("/ws").to(route::chat_route))
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+            .route("/healthz", web::get().to(health::healthz))
+            .route("/readyz", web::get().to(health::readyz))
+            .configure(|cfg| {
+                if features.metrics {
+                    cfg.route("/metrics", web::get().to(metrics::export));
+                }
+            })
+    };
+    // 信号由 `drain` 处理, 先关闭 WebSocket 会话再停止 http 服务
+    let mut server = Server::build()
+        .disable_signals()
+        .shutdown_timeout(shutdown_timeout.as_secs());
+    if workers > 0 {
+        server = server.workers(workers);
+    }
+    for addr in &binds {
+        let app = app.clone();
+        server = server.bind(format!("http-{}", addr), addr, move || {
+            HttpService::build()
+                .finish(map_config(app(), |_| AppConfig::default()))
+                .tcp()
+        })?;
+    }
+    // 同一个 App 也在 tls.bind 上提供 https 和 wss
+    if let Some(acceptor) = acceptor {
+        for addr in &tls_binds {
+            let (app, acceptor) = (app.clone(), acceptor.clone());
+            server = server.bind(format!("https-{}", addr), addr, move || {
+                tls::https(acceptor.clone(), app())
+            })?;
+        }
+    }
+    let server = server.run();
+    let stop = {
+        let server = server.clone();
+        move || {
+            drain(
+                server.clone(),
+                chat_server.clone(),
+                drain_flag.clone(),
+                shutdown_timeout,
+                reconnect_after,
+            )
+        }
+    };
+    let on_interrupt = stop.clone();
+    actix_rt::spawn(async move {
+        if actix_rt::signal::ctrl_c().await.is_ok() {
+            on_interrupt().await;
+        }
+    });
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        let on_terminate = stop.clone();
+        actix_rt::spawn(async move {
+            if terminate.recv().await.is_some() {
+                on_terminate().await;
+            }
+        });
+    }
+    server.await
+}
+
+// 不再接受 `/ws` 升级, 通知所有会话稍后重连, 等连接关闭、消息落库后停止 http 服务;
+// 超过 `server.shutdown_timeout` 时强制关闭剩余连接
+async fn drain(
+    server: Server,
+    chat_server: Addr<server::ChatServer>,
+    draining: web::Data<Draining>,
+    timeout: Duration,
+    reconnect_after: Duration,
+) {
+    if !draining.start() {
+        return;
+    }
+    info!("shutting down, draining websocket sessions");
+    let graceful = async {
+        let shutdown = server::Shutdown {
+            reconnect_after: reconnect_after.as_secs(),
+        };
+        match chat_server.send(shutdown).await {
+            Ok(n) => info!(sessions = n, "asked websocket sessions to reconnect"),
+            Err(e) => warn!(error = %e, "chat server unavailable"),
+        }
+        server.stop(true).await;
+        // 等已收到的消息落库
+        while let Ok(n) = chat_server.send(server::Pending).await {
+            if n == 0 {
+                break;
+            }
+            actix_rt::time::delay_for(Duration::from_millis(50)).await;
+        }
+    };
+    if actix_rt::time::timeout(timeout, graceful).await.is_err() {
+        warn!("shutdown timed out, closing remaining connections");
+        server.stop(false).await;
+    }
+}
+
+// 启动失败时打印原因并退出
+fn exit_with(what: &str, e: impl Display) -> ! {
+    eprintln!("{}: {}", what, e);
+    process::exit(1)
 }
@@ -0,0 +1,212 @@
+use crate::db::{RedisPool, Repository};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+// 进程的 Prometheus 指标, 由 `GET /metrics` 提供
+// 计数器和直方图在发生处更新, 连接池的 gauge 在抓取时读取
+pub struct Metrics {
+    registry: Registry,
+    pub sessions: IntGauge,
+    pub rooms: IntGauge,
+    // 按 `ChatMessageType` 分类, 通过过滤和处罚检查后投递的消息
+    pub messages_routed: IntCounterVec,
+    // 没有投递的消息, 按原因分类
+    pub messages_dropped: IntCounterVec,
+    // 超过 `websocket.max_frame_size` 的帧和超过 content 上限的消息
+    pub messages_oversized: IntCounterVec,
+    // 收到帧到回复 ack
+    pub ack_latency: Histogram,
+    pub heartbeat_timeouts: IntCounter,
+    pub db_duration: HistogramVec,
+    pub redis_duration: HistogramVec,
+    // 标签为连接池和状态 (idle/active)
+    pub pool_connections: IntGaugeVec,
+    pub http_duration: HistogramVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+// 毫秒级到秒级
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry =
+            Registry::new_custom(Some("chat".to_owned()), None).expect("metrics registry");
+        let histogram = |name: &str, help: &str| {
+            HistogramOpts::new(name, help).buckets(LATENCY_BUCKETS.to_vec())
+        };
+        let metrics = Metrics {
+            sessions: IntGauge::new("sessions", "connected websocket sessions").unwrap(),
+            rooms: IntGauge::new("rooms", "rooms with at least one connected session").unwrap(),
+            messages_routed: IntCounterVec::new(
+                Opts::new("messages_routed_total", "messages delivered, by type"),
+                &["type"],
+            )
+            .unwrap(),
+            messages_dropped: IntCounterVec::new(
+                Opts::new(
+                    "messages_dropped_total",
+                    "messages not delivered, by reason",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            messages_oversized: IntCounterVec::new(
+                Opts::new(
+                    "messages_oversized_total",
+                    "frames and messages rejected for their size, by type",
+                ),
+                &["type"],
+            )
+            .unwrap(),
+            ack_latency: Histogram::with_opts(histogram(
+                "ack_latency_seconds",
+                "time from receiving a frame to acknowledging it",
+            ))
+            .unwrap(),
+            heartbeat_timeouts: IntCounter::new(
+                "heartbeat_timeouts_total",
+                "sessions closed for missing heartbeats",
+            )
+            .unwrap(),
+            db_duration: HistogramVec::new(
+                histogram(
+                    "db_query_duration_seconds",
+                    "database calls, through `Storage` or direct queries",
+                ),
+                &["kind"],
+            )
+            .unwrap(),
+            redis_duration: HistogramVec::new(
+                histogram(
+                    "redis_command_duration_seconds",
+                    "redis calls, by operation",
+                ),
+                &["op"],
+            )
+            .unwrap(),
+            pool_connections: IntGaugeVec::new(
+                Opts::new("pool_connections", "connections of the pools, by state"),
+                &["pool", "state"],
+            )
+            .unwrap(),
+            http_duration: HistogramVec::new(
+                histogram("http_request_duration_seconds", "http requests"),
+                &["method", "path", "status"],
+            )
+            .unwrap(),
+            registry,
+        };
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.sessions.clone()),
+            Box::new(metrics.rooms.clone()),
+            Box::new(metrics.messages_routed.clone()),
+            Box::new(metrics.messages_dropped.clone()),
+            Box::new(metrics.messages_oversized.clone()),
+            Box::new(metrics.ack_latency.clone()),
+            Box::new(metrics.heartbeat_timeouts.clone()),
+            Box::new(metrics.db_duration.clone()),
+            Box::new(metrics.redis_duration.clone()),
+            Box::new(metrics.pool_connections.clone()),
+            Box::new(metrics.http_duration.clone()),
+        ];
+        for c in collectors {
+            metrics.registry.register(c).expect("register metric");
+        }
+        metrics
+    }
+
+    pub fn routed(&self, message_type: &str) {
+        self.messages_routed
+            .with_label_values(&[message_type])
+            .inc();
+    }
+
+    pub fn dropped(&self, reason: &str) {
+        self.messages_dropped.with_label_values(&[reason]).inc();
+    }
+
+    // 也计入 `messages_dropped_total{reason="oversized"}`
+    pub fn oversized(&self, message_type: &str) {
+        self.messages_oversized
+            .with_label_values(&[message_type])
+            .inc();
+        self.dropped("oversized");
+    }
+
+    fn set_pool(&self, pool: &str, state: r2d2::State) {
+        let idle = i64::from(state.idle_connections);
+        let total = i64::from(state.connections);
+        self.pool_connections
+            .with_label_values(&[pool, "idle"])
+            .set(idle);
+        self.pool_connections
+            .with_label_values(&[pool, "active"])
+            .set(total - idle);
+    }
+}
+
+// GET /metrics
+pub async fn export(repo: web::Data<Repository>, redis: web::Data<RedisPool>) -> HttpResponse {
+    if let Some(state) = repo.pool_state() {
+        METRICS.set_pool("db", state);
+    }
+    METRICS.set_pool("redis", redis.state());
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    match encoder.encode(&METRICS.registry.gather(), &mut body) {
+        Ok(_) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(body),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// 记录每个 http 请求的耗时, 路径中的参数换成参数名, 避免 id 等产生过多的标签
+pub fn track_http<S, B>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let fut = srv.call(req);
+    async move {
+        let res = fut.await?;
+        let path = route_of(res.request());
+        METRICS
+            .http_duration
+            .with_label_values(&[&method, &path, res.status().as_str()])
+            .observe(start.elapsed().as_secs_f64());
+        Ok(res)
+    }
+}
+
+// 路径归一化为路由模板, 如 `/api/users/100001/profile` -> `/api/users/{user_id}/profile`
+fn route_of(req: &HttpRequest) -> String {
+    if !req.resource_map().has_resource(req.path()) {
+        return "unmatched".to_owned();
+    }
+    let params: Vec<(&str, &str)> = req.match_info().iter().collect();
+    req.path()
+        .split('/')
+        .map(|segment| match params.iter().find(|(_, v)| *v == segment) {
+            Some((name, _)) => format!("{{{}}}", name),
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,336 @@
+use crate::config::{Config, LogFormat};
+use actix::prelude::*;
+use actix_web::client::Client;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use serde_json::{json, Value};
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::field::{Empty, Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info, info_span, warn, Subscriber};
+use tracing_futures::Instrument;
+use tracing_subscriber::layer::{Context as LayerContext, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{fmt as logfmt, prelude::*, EnvFilter};
+
+// 每批最多导出的 span 数, 不足时按间隔导出
+const MAX_BATCH: usize = 512;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+// 安装全局 subscriber: 按 `server.log` 过滤, 以 `server.log_format` 格式输出日志,
+// 设置了 `tracing.otlp_endpoint` 时把 span 导出到该 OTLP/HTTP collector.
+// `log` crate 的事件 (actix) 也经过它.
+// 必须在 actix system 内调用, 导出器运行在单独的 arbiter 上
+pub fn init(config: &Config) {
+    let filter = EnvFilter::new(&config.server.log);
+    let otlp = config.tracing.otlp_endpoint.as_ref().map(|endpoint| {
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let service = config.tracing.service_name.clone();
+        let exporter = OtlpExporter::start_in_arbiter(&Arbiter::new(), move |_| OtlpExporter {
+            url,
+            service,
+            interval: EXPORT_INTERVAL,
+            batch: Vec::new(),
+        });
+        OtlpLayer { exporter }
+    });
+    let registry = tracing_subscriber::registry().with(filter).with(otlp);
+    match config.server.log_format {
+        LogFormat::Json => registry.with(logfmt::layer().json()).init(),
+        LogFormat::Text => registry.with(logfmt::layer()).init(),
+    }
+}
+
+// 每个 http 请求一个 span, 处理器中的数据库调用等记在它下面, 结束时输出一条访问日志
+pub fn trace_http<S, B>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let span = info_span!(
+        "http_request",
+        method = %req.method(),
+        path = req.path(),
+        remote_addr = req.connection_info().remote().unwrap_or("-"),
+        status = Empty,
+    );
+    let fut = span.in_scope(|| srv.call(req));
+    async move {
+        let res = fut.await?;
+        let status = res.status().as_u16();
+        tracing::Span::current().record("status", status);
+        info!(status, "request finished");
+        Ok(res)
+    }
+    .instrument(span)
+}
+
+// span 的导出数据, 记录在 span 的 extensions 中, 关闭时发给 OtlpExporter
+struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    name: &'static str,
+    start: SystemTime,
+    attributes: Vec<Value>,
+}
+
+impl SpanData {
+    // OTLP JSON 编码: id 为十六进制, 时间为字符串形式的纳秒
+    fn into_json(self, end: SystemTime) -> Value {
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.trace_id),
+            "spanId": format!("{:016x}", self.span_id),
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(end),
+            "attributes": self.attributes,
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = json!(format!("{:016x}", parent));
+        }
+        span
+    }
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+        .to_string()
+}
+
+struct FieldVisitor<'a>(&'a mut Vec<Value>);
+
+impl FieldVisitor<'_> {
+    fn push(&mut self, field: &Field, value: Value) {
+        self.0.push(json!({ "key": field.name(), "value": value }));
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, json!({ "boolValue": value }));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, json!({ "stringValue": value }));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, json!({ "stringValue": format!("{:?}", value) }));
+    }
+}
+
+// 把关闭的 span 转为 OTLP span, trace id 继承自父 span, 根 span 开始新的 trace
+struct OtlpLayer {
+    exporter: Addr<OtlpExporter>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: LayerContext<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let parent = span.parent().and_then(|p| {
+            p.extensions()
+                .get::<SpanData>()
+                .map(|d| (d.trace_id, d.span_id))
+        });
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (rand::random(), None),
+        };
+        let mut data = SpanData {
+            trace_id,
+            span_id: rand::random(),
+            parent_span_id,
+            name: attrs.metadata().name(),
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+        };
+        attrs.record(&mut FieldVisitor(&mut data.attributes));
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: LayerContext<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(&mut FieldVisitor(&mut data.attributes));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: LayerContext<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(data) = span.extensions_mut().remove::<SpanData>() {
+                self.exporter
+                    .do_send(ExportSpan(data.into_json(SystemTime::now())));
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ExportSpan(Value);
+
+// 批量以 OTLP/HTTP JSON 发送 span 到 collector, 无法连接时丢弃
+struct OtlpExporter {
+    url: String,
+    service: String,
+    // 测试中缩短
+    interval: Duration,
+    batch: Vec<Value>,
+}
+
+impl OtlpExporter {
+    fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let spans = std::mem::take(&mut self.batch);
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{
+                        "key": "service.name",
+                        "value": { "stringValue": self.service },
+                    }],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME") },
+                    "spans": spans,
+                }],
+            }],
+        });
+        let request = Client::default().post(&self.url).send_json(&body);
+        actix_rt::spawn(async move {
+            match request.await {
+                Ok(res) if res.status().is_success() => (),
+                Ok(res) => warn!(status = res.status().as_u16(), "otlp export rejected"),
+                Err(e) => warn!(error = %e, "otlp export failed"),
+            }
+        });
+    }
+}
+
+impl Actor for OtlpExporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, _| act.flush());
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.flush();
+        Running::Stop
+    }
+}
+
+impl Handler<ExportSpan> for OtlpExporter {
+    type Result = ();
+
+    fn handle(&mut self, msg: ExportSpan, _: &mut Self::Context) {
+        self.batch.push(msg.0);
+        if self.batch.len() >= MAX_BATCH {
+            self.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_rt::time::delay_for;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+
+    // 代替 collector, 收到的请求体交给测试
+    async fn collect(
+        tx: web::Data<Mutex<mpsc::Sender<Value>>>,
+        body: web::Json<Value>,
+    ) -> HttpResponse {
+        tx.lock().unwrap().send(body.into_inner()).ok();
+        HttpResponse::Ok().finish()
+    }
+
+    fn attribute<'a>(span: &'a Value, key: &str) -> &'a Value {
+        let attributes = span["attributes"].as_array().unwrap();
+        &attributes.iter().find(|a| a["key"] == key).unwrap()["value"]
+    }
+
+    #[actix_rt::test]
+    async fn exports_spans_to_collector() {
+        let (tx, rx) = mpsc::channel::<Value>();
+        let server = HttpServer::new(move || {
+            App::new()
+                .data(Mutex::new(tx.clone()))
+                .route("/v1/traces", web::post().to(collect))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+
+        let exporter = OtlpExporter {
+            url: format!("http://{}/v1/traces", addr),
+            service: "chat_test".to_owned(),
+            interval: Duration::from_millis(50),
+            batch: Vec::new(),
+        }
+        .start();
+        let subscriber = tracing_subscriber::registry().with(OtlpLayer { exporter });
+        tracing::subscriber::with_default(subscriber, || {
+            let request = info_span!("http_request", user_id = 7);
+            request.in_scope(|| info_span!("db_query", kind = "select").in_scope(|| ()));
+        });
+
+        let mut body = None;
+        for _ in 0..100 {
+            if let Ok(b) = rx.try_recv() {
+                body = Some(b);
+                break;
+            }
+            delay_for(Duration::from_millis(50)).await;
+        }
+        server.stop(false).await;
+        let body = body.expect("no export within 5 seconds");
+
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "chat_test"
+        );
+        // 子 span 先关闭
+        let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 2);
+        let (query, request) = (&spans[0], &spans[1]);
+        assert_eq!(query["name"], "db_query");
+        assert_eq!(request["name"], "http_request");
+        assert_eq!(query["traceId"], request["traceId"]);
+        assert_eq!(query["parentSpanId"], request["spanId"]);
+        assert!(request.get("parentSpanId").is_none());
+        assert_eq!(attribute(request, "user_id")["intValue"], "7");
+        assert_eq!(attribute(query, "kind")["stringValue"], "select");
+    }
+}
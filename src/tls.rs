@@ -0,0 +1,173 @@
+use crate::config::TlsConfig;
+use actix_http::body::MessageBody;
+use actix_http::error::DispatchError;
+use actix_http::http::uri::{Authority, Scheme};
+use actix_http::http::{header, Uri};
+use actix_http::{Error, HttpService, Protocol, Request, Response};
+use actix_rt::net::TcpStream;
+use actix_service::{
+    apply_fn_factory, fn_service, map_config, pipeline_factory, IntoServiceFactory, Service,
+    ServiceFactory,
+};
+use actix_web::dev::AppConfig;
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig, Session};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+// 读取 PEM 格式的证书链和私钥, 私钥依次尝试 PKCS#8 和 RSA
+pub fn load(cert: &str, key: &str) -> Result<CertifiedKey, String> {
+    let file = fs::File::open(cert).map_err(|e| format!("{}: {}", cert, e))?;
+    let certs = pemfile::certs(&mut BufReader::new(file))
+        .map_err(|_| format!("{}: invalid PEM certificate", cert))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificate found", cert));
+    }
+    let pem = fs::read(key).map_err(|e| format!("{}: {}", key, e))?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut pem.as_slice())
+        .map_err(|_| format!("{}: invalid PEM private key", key))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut pem.as_slice())
+            .map_err(|_| format!("{}: invalid PEM private key", key))?;
+    }
+    let der = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("{}: no private key found", key))?;
+    let signing = sign::any_supported_type(&der)
+        .map_err(|_| format!("{}: unsupported private key type", key))?;
+    Ok(CertifiedKey::new(certs, Arc::new(signing)))
+}
+
+// 每次握手取当前证书; 文件变化时由 `watch` 替换, 已建立的连接不受影响
+struct CertResolver {
+    current: RwLock<CertifiedKey>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<CertifiedKey> {
+        self.current.read().ok().map(|k| k.clone())
+    }
+}
+
+// `tls.bind` 监听使用的 acceptor, 同时开始监视证书文件; 需在 actix 系统内调用
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let cert = config.cert.clone().unwrap_or_default();
+    let key = config.key.clone().unwrap_or_default();
+    let resolver = Arc::new(CertResolver {
+        current: RwLock::new(load(&cert, &key)?),
+    });
+    let mut server = ServerConfig::new(NoClientAuth::new());
+    server.cert_resolver = resolver.clone();
+    server.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    watch(resolver, cert, key, config.reload_interval);
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
+
+// 按间隔检查证书和私钥的修改时间. 两个文件通常先后替换, 修改时间在一个间隔内
+// 不再变化才重新加载; 加载失败时继续使用当前证书
+fn watch(resolver: Arc<CertResolver>, cert: String, key: String, interval: Duration) {
+    actix_rt::spawn(async move {
+        let mut loaded = modified(&cert, &key);
+        let mut seen = loaded;
+        let mut ticks = actix_rt::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            let now = modified(&cert, &key);
+            if now != seen {
+                seen = now;
+                continue;
+            }
+            if now == loaded {
+                continue;
+            }
+            loaded = now;
+            match load(&cert, &key) {
+                Ok(k) => {
+                    if let Ok(mut current) = resolver.current.write() {
+                        *current = k;
+                    }
+                    info!(cert = %cert, "reloaded tls certificate");
+                }
+                Err(e) => {
+                    warn!(error = %e, "reload tls certificate failed, keeping the current one")
+                }
+            }
+        }
+    });
+}
+
+fn modified(cert: &str, key: &str) -> Option<(SystemTime, SystemTime)> {
+    let cert = fs::metadata(cert).and_then(|m| m.modified()).ok()?;
+    let key = fs::metadata(key).and_then(|m| m.modified()).ok()?;
+    Some((cert, key))
+}
+
+// 一个 https 监听, 对应 `HttpService::tcp`
+pub fn https<S, B>(
+    acceptor: TlsAcceptor,
+    app: impl IntoServiceFactory<S>,
+) -> impl ServiceFactory<
+    Config = (),
+    Request = TcpStream,
+    Response = (),
+    Error = DispatchError,
+    InitError = (),
+>
+where
+    S: ServiceFactory<Config = AppConfig, Request = Request>,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    pipeline_factory(fn_service(move |io: TcpStream| {
+        let accept = acceptor.accept(io);
+        async move {
+            let io = accept.await.map_err(DispatchError::Io)?;
+            let proto = match io.get_ref().1.get_alpn_protocol() {
+                Some(p) if p == b"h2" => Protocol::Http2,
+                _ => Protocol::Http1,
+            };
+            let peer_addr = io.get_ref().0.peer_addr().ok();
+            Ok((io, proto, peer_addr))
+        }
+    }))
+    .and_then(HttpService::build().finish(apply_fn_factory(
+        map_config(app, |_| AppConfig::default()),
+        |mut req: Request, srv: &mut S::Service| {
+            absolute(&mut req);
+            srv.call(req)
+        },
+    )))
+}
+
+// actix-web 2 的 AppConfig 不能设置 secure, HTTP/1 的请求行又只有路径,
+// 补上 https 和 Host, `ConnectionInfo::scheme` 才能报告 https
+fn absolute(req: &mut Request) {
+    let head = req.head_mut();
+    if head.uri.scheme().is_some() {
+        return;
+    }
+    let authority = match head.headers.get(header::HOST).map(|h| h.as_bytes()) {
+        Some(host) => match Authority::try_from(host) {
+            Ok(authority) => authority,
+            Err(_) => return,
+        },
+        None => return,
+    };
+    let mut parts = head.uri.clone().into_parts();
+    parts.scheme = Some(Scheme::HTTPS);
+    parts.authority = Some(authority);
+    if let Ok(uri) = Uri::from_parts(parts) {
+        head.uri = uri;
+    }
+}
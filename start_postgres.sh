@@ -0,0 +1,11 @@
+#!/bin/sh
+
+# shellcheck disable=SC2164
+CURRENT_FOLDER=$(cd "$(dirname "$0")";pwd)
+docker run --name chat-postgres \
+  -p 5432:5432 \
+  -v $CURRENT_FOLDER/postgres/data:/var/lib/postgresql/data \
+  -e POSTGRES_USER=chat \
+  -e POSTGRES_PASSWORD=chat \
+  -e POSTGRES_DB=chat \
+  -d postgres:latest
\ No newline at end of file